@@ -0,0 +1,250 @@
+use std::{
+    error::Error,
+    fs::{metadata, read_to_string},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+
+use crate::api_misskey::User;
+
+/// Users are written as the user id, `@username` for local users, or `@username@host`.
+#[derive(PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessList {
+    /// Remote instances whose users can request invites.
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+    #[serde(default)]
+    pub deny_hosts: Vec<String>,
+    /// Remote users who can request invites, even if their instances are not allowed.
+    #[serde(default)]
+    pub allow_users: Vec<String>,
+    #[serde(default)]
+    pub deny_users: Vec<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Access {
+    Allowed,
+    DeniedUser,
+    DeniedHost,
+    /// The user is remote, and neither the user nor the instance is allowed.
+    RemoteUser,
+}
+
+pub fn check_access(list: &AccessList, user: &User) -> Access {
+    let is_user =
+        |entry: &String| is_same_user(entry, &user.id, &user.username, user.host.as_deref());
+
+    if list.deny_users.iter().any(is_user) {
+        return Access::DeniedUser;
+    }
+
+    let Some(host) = &user.host else {
+        return Access::Allowed;
+    };
+
+    if list.deny_hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+        Access::DeniedHost
+    } else if list.allow_users.iter().any(is_user)
+        || list
+            .allow_hosts
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(host))
+    {
+        Access::Allowed
+    } else {
+        Access::RemoteUser
+    }
+}
+
+/// Whether the entry, written as the user id, `@username` or `@username@host`, points to the user.
+pub fn is_same_user(entry: &str, id: &str, username: &str, host: Option<&str>) -> bool {
+    if entry == id {
+        return true;
+    }
+
+    let Some(acct) = entry.strip_prefix('@') else {
+        return false;
+    };
+    let (entry_username, entry_host) = match acct.split_once('@') {
+        Some((username, host)) => (username, Some(host)),
+        None => (acct, None),
+    };
+
+    entry_username.eq_ignore_ascii_case(username)
+        && match (entry_host, host) {
+            (None, None) => true,
+            (Some(entry_host), Some(host)) => entry_host.eq_ignore_ascii_case(host),
+            _ => false,
+        }
+}
+
+/// The access list file, read again when it is modified.
+pub struct AccessListFile {
+    path: String,
+    /// Modified time of the file read last time, and the last list read successfully.
+    cache: Mutex<(SystemTime, Arc<AccessList>)>,
+}
+
+impl AccessListFile {
+    /// Read the file, which must exist and be valid at startup.
+    pub fn open(path: String) -> Result<AccessListFile, Box<dyn Error>> {
+        let (modified, list) = read(&path)?;
+
+        Ok(AccessListFile {
+            path,
+            cache: Mutex::new((modified, Arc::new(list))),
+        })
+    }
+
+    /// The current list. If the file is modified but cannot be read, such as while it is edited,
+    /// the last good one is used until the file is modified again.
+    pub fn load(&self) -> Arc<AccessList> {
+        let mut cache = self.cache.lock().unwrap();
+
+        let modified = metadata(&self.path).and_then(|metadata| metadata.modified());
+        if modified.as_ref().is_ok_and(|modified| *modified == cache.0) {
+            return Arc::clone(&cache.1);
+        }
+
+        match read(&self.path) {
+            Ok((modified, list)) => {
+                log::info!("Loaded access list from {}.", self.path);
+                *cache = (modified, Arc::new(list));
+            }
+            Err(err) => {
+                log::warn!(
+                    "Failed to reload access list from {}, using the last one: {}",
+                    self.path,
+                    err
+                );
+                if let Ok(modified) = modified {
+                    cache.0 = modified;
+                }
+            }
+        }
+
+        Arc::clone(&cache.1)
+    }
+}
+
+fn read(path: &str) -> Result<(SystemTime, AccessList), Box<dyn Error>> {
+    let modified = metadata(path)?.modified()?;
+    let list = toml::from_str(&read_to_string(path)?)?;
+
+    Ok((modified, list))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str, username: &str, host: Option<&str>) -> User {
+        User {
+            id: id.to_string(),
+            username: username.to_string(),
+            host: host.map(str::to_string),
+        }
+    }
+
+    fn list() -> AccessList {
+        toml::from_str(
+            r#"
+            allow_hosts = ["partner.example"]
+            deny_hosts = ["spam.example"]
+            allow_users = ["@friend@other.example"]
+            deny_users = ["@blocked", "blocked-id", "@Troll@Partner.example"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn local_users() {
+        assert_eq!(
+            check_access(&list(), &user("id", "user", None)),
+            Access::Allowed
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "blocked", None)),
+            Access::DeniedUser
+        );
+        assert_eq!(
+            check_access(&list(), &user("blocked-id", "user", None)),
+            Access::DeniedUser
+        );
+    }
+
+    #[test]
+    fn reload_file() {
+        let path = std::env::temp_dir().join(format!(
+            "discord_inviter_misskey_access_list_{}",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap().to_string();
+        let write = |content: &str, modified_secs: u64| {
+            std::fs::write(&path, content).unwrap();
+            let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(modified_secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        write("deny_users = [\"@a\"]", 100);
+        let file = AccessListFile::open(path_str.clone()).unwrap();
+        assert_eq!(file.load().deny_users, ["@a"]);
+
+        write("deny_users = [\"@b\"]", 200);
+        assert_eq!(file.load().deny_users, ["@b"]);
+
+        // Broken or removed files keep the last good list.
+        write("deny_users = [", 300);
+        assert_eq!(file.load().deny_users, ["@b"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.load().deny_users, ["@b"]);
+
+        // But must be valid at startup.
+        assert!(AccessListFile::open(path_str).is_err());
+    }
+
+    #[test]
+    fn remote_users() {
+        assert_eq!(
+            check_access(&list(), &user("id", "user", Some("partner.example"))),
+            Access::Allowed
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "troll", Some("partner.example"))),
+            Access::DeniedUser
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "blocked", Some("partner.example"))),
+            Access::Allowed
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "user", Some("spam.example"))),
+            Access::DeniedHost
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "friend", Some("other.example"))),
+            Access::Allowed
+        );
+        assert_eq!(
+            check_access(&list(), &user("id", "user", Some("other.example"))),
+            Access::RemoteUser
+        );
+        assert_eq!(
+            check_access(
+                &AccessList::default(),
+                &user("id", "user", Some("other.example"))
+            ),
+            Access::RemoteUser
+        );
+    }
+}
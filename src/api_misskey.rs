@@ -1,7 +1,8 @@
 use std::fmt::Display;
 
+use chrono::{DateTime, Utc};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -19,6 +20,56 @@ pub struct User {
     pub host: Option<String>,
 }
 
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDetailed {
+    pub id: String,
+    pub username: String,
+    pub host: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub notes_count: u64,
+    pub followers_count: u64,
+    pub following_count: u64,
+    /// Public roles of the user.
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub is_silenced: bool,
+    #[serde(default)]
+    pub is_suspended: bool,
+    /// Language set in the account, such as `ja-JP`.
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Role {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub user_id: Option<String>,
+    pub note: Option<Note>,
+    pub reaction: Option<String>,
+}
+
+/// Relation from the bot account to the user.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRelation {
+    pub id: String,
+    /// The bot follows the user.
+    pub is_following: bool,
+    /// The user follows the bot.
+    pub is_followed: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Error {
     pub error_body: String,
@@ -32,6 +83,24 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Error code such as `NO_SUCH_USER`, if the body is the error of Misskey API.
+    pub fn code(&self) -> Option<String> {
+        #[derive(Deserialize)]
+        struct Body {
+            error: Code,
+        }
+        #[derive(Deserialize)]
+        struct Code {
+            code: String,
+        }
+
+        serde_json::from_str::<Body>(&self.error_body)
+            .ok()
+            .map(|body| body.error.code)
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct PostParams<'a, T> {
     pub i: &'a str,
@@ -50,14 +119,46 @@ pub struct NotesCreateParams<'a> {
     pub reply_id: Option<&'a str>,
 }
 
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotesCreateResponse {
+    pub created_note: Note,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotesShowParams<'a> {
+    pub note_id: &'a str,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotesMentionsParams<'a> {
+    pub limit: u32,
+    pub since_id: Option<&'a str>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsersShowParams<'a> {
+    pub user_id: &'a str,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsersRelationParams<'a> {
+    pub user_id: &'a str,
+}
+
 pub struct MisskeyApi {
     client: Client,
-    host: String,
+    /// Such as `https://example.com`.
+    base_url: String,
     token: String,
 }
 
 impl MisskeyApi {
-    pub fn new(host: String, token: String) -> Self {
+    pub fn new(base_url: String, token: String) -> Self {
         let client = Client::builder()
             .user_agent(env!("CARGO_PKG_NAME"))
             .pool_max_idle_per_host(0) // api server close connection in about 90 secs
@@ -66,18 +167,19 @@ impl MisskeyApi {
             .unwrap();
         MisskeyApi {
             client,
-            host,
+            base_url,
             token,
         }
     }
 
-    async fn post<T>(&self, endpoint: &str, body: T) -> Result<(), Box<dyn std::error::Error>>
+    async fn post<T, R>(&self, endpoint: &str, body: T) -> Result<R, Box<dyn std::error::Error>>
     where
         T: Serialize,
+        R: DeserializeOwned,
     {
         let r = self
             .client
-            .post(format!("https://{}/api/{}", self.host, endpoint))
+            .post(format!("{}/api/{}", self.base_url, endpoint))
             .json(&body)
             .send()
             .await?;
@@ -88,17 +190,61 @@ impl MisskeyApi {
             return Err(Box::new(Error { error_body }));
         }
 
-        Ok(())
+        Ok(r.json().await?)
     }
 
     pub async fn notes_create(
         &self,
         params: NotesCreateParams<'_>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<NotesCreateResponse, Box<dyn std::error::Error>> {
         let with_token = PostParams {
             i: &self.token,
             body: params,
         };
         self.post("notes/create", with_token).await
     }
+
+    pub async fn notes_show(
+        &self,
+        params: NotesShowParams<'_>,
+    ) -> Result<Note, Box<dyn std::error::Error>> {
+        let with_token = PostParams {
+            i: &self.token,
+            body: params,
+        };
+        self.post("notes/show", with_token).await
+    }
+
+    pub async fn notes_mentions(
+        &self,
+        params: NotesMentionsParams<'_>,
+    ) -> Result<Vec<Note>, Box<dyn std::error::Error>> {
+        let with_token = PostParams {
+            i: &self.token,
+            body: params,
+        };
+        self.post("notes/mentions", with_token).await
+    }
+
+    pub async fn users_show(
+        &self,
+        params: UsersShowParams<'_>,
+    ) -> Result<UserDetailed, Box<dyn std::error::Error>> {
+        let with_token = PostParams {
+            i: &self.token,
+            body: params,
+        };
+        self.post("users/show", with_token).await
+    }
+
+    pub async fn users_relation(
+        &self,
+        params: UsersRelationParams<'_>,
+    ) -> Result<UserRelation, Box<dyn std::error::Error>> {
+        let with_token = PostParams {
+            i: &self.token,
+            body: params,
+        };
+        self.post("users/relation", with_token).await
+    }
 }
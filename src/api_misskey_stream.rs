@@ -4,7 +4,7 @@ use async_tungstenite::{tokio::connect_async, tungstenite::Message};
 use futures::{lock::Mutex, Future, SinkExt, StreamExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::api_misskey::Note;
+use crate::api_misskey::{Note, Notification};
 
 #[derive(PartialEq, Eq, Debug, Serialize)]
 #[serde(tag = "type", content = "body")]
@@ -47,6 +47,8 @@ pub struct StreamingChannel<Body> {
 #[serde(rename_all = "lowercase")]
 pub enum StreamingBodyMain {
     Mention(Note),
+    Reply(Note),
+    Notification(Notification),
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -57,13 +59,14 @@ pub enum StreamingBodyTimeline {
 }
 
 pub struct MisskeyApiStream {
-    host: String,
+    /// Such as `wss://example.com`.
+    base_url: String,
     token: String,
 }
 
 impl MisskeyApiStream {
-    pub fn new(host: String, token: String) -> Self {
-        MisskeyApiStream { host, token }
+    pub fn new(base_url: String, token: String) -> Self {
+        MisskeyApiStream { base_url, token }
     }
 
     async fn start<P, B, F1, F2>(
@@ -78,7 +81,7 @@ impl MisskeyApiStream {
         F1: Future<Output = ()>,
         F2: Future<Output = ()>,
     {
-        let url = format!("wss://{}/streaming?i={}", self.host, self.token);
+        let url = format!("{}/streaming?i={}", self.base_url, self.token);
         let (ws, _) = connect_async(url).await?;
         let (mut sink, mut stream) = ws.split();
 
@@ -91,7 +94,7 @@ impl MisskeyApiStream {
 
         let sink = Arc::new(Mutex::new(sink));
 
-        let pinging = (|| async {
+        let pinging = async {
             loop {
                 let result = sink.lock().await.send(Message::Ping(Vec::new())).await;
                 match result {
@@ -117,10 +120,13 @@ impl MisskeyApiStream {
             }
 
             Ok::<(), Box<dyn Error>>(())
-        })();
+        };
         tokio::pin!(pinging);
 
-        on_ready().await;
+        // Runs alongside the stream, which keeps answering pings meanwhile.
+        let ready = on_ready();
+        tokio::pin!(ready);
+        let mut is_ready = false;
 
         loop {
             tokio::select! {
@@ -128,6 +134,9 @@ impl MisskeyApiStream {
                 result = &mut pinging => {
                     return result
                 },
+                _ = &mut ready, if !is_ready => {
+                    is_ready = true;
+                },
                 stream_next = stream.next() => {
                     match stream_next {
                         Some(event) => {
@@ -0,0 +1,70 @@
+use crate::config::ApprovalConfig;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Decision {
+    Approve,
+    Deny,
+}
+
+/// Moderator's decision written in the reply to the notice, such as `@bot approve`.
+pub fn decision_from_reply(text: &str) -> Option<Decision> {
+    // Skip mentions which the client inserts at the top.
+    let word = text
+        .split_whitespace()
+        .find(|word| !word.starts_with('@'))?;
+
+    match word.to_lowercase().as_str() {
+        "approve" => Some(Decision::Approve),
+        "deny" => Some(Decision::Deny),
+        _ => None,
+    }
+}
+
+pub fn decision_from_reaction(config: &ApprovalConfig, reaction: &str) -> Option<Decision> {
+    if config.approve_reactions.iter().any(|r| r == reaction) {
+        Some(Decision::Approve)
+    } else if config.deny_reactions.iter().any(|r| r == reaction) {
+        Some(Decision::Deny)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply() {
+        assert_eq!(decision_from_reply("approve"), Some(Decision::Approve));
+        assert_eq!(
+            decision_from_reply("@bot @mod Approve it"),
+            Some(Decision::Approve)
+        );
+        assert_eq!(decision_from_reply("@bot deny"), Some(Decision::Deny));
+        assert_eq!(decision_from_reply("@bot thanks"), None);
+        assert_eq!(decision_from_reply("@bot"), None);
+    }
+
+    #[test]
+    fn reaction() {
+        let config = ApprovalConfig {
+            moderator_user_ids: vec![],
+            approve_reactions: vec!["👍".to_string()],
+            deny_reactions: vec![":deny@.:".to_string()],
+            message_notice: String::new(),
+            message_pending: String::new(),
+            message_denied: String::new(),
+        };
+
+        assert_eq!(
+            decision_from_reaction(&config, "👍"),
+            Some(Decision::Approve)
+        );
+        assert_eq!(
+            decision_from_reaction(&config, ":deny@.:"),
+            Some(Decision::Deny)
+        );
+        assert_eq!(decision_from_reaction(&config, "👎"), None);
+    }
+}
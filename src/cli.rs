@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+/// Command line arguments.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Args {
+    /// Path to the config file, `bot_config.toml` in the working directory by default.
+    pub config_path: PathBuf,
+    /// Only validate the config, and exit.
+    pub check_config: bool,
+}
+
+pub const USAGE: &str = "Usage: discord_inviter_misskey [--config <path>] [--check-config]";
+
+impl Args {
+    /// Parse the arguments, excluding the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut config_path = PathBuf::from("bot_config.toml");
+        let mut check_config = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.split_once('=') {
+                Some(("--config", path)) => config_path = path.into(),
+                _ if arg == "--check-config" => check_config = true,
+                _ if arg == "--config" => {
+                    config_path = args
+                        .next()
+                        .ok_or_else(|| "--config needs the path".to_string())?
+                        .into();
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(Args {
+            config_path,
+            check_config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Args;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn config_path() {
+        assert_eq!(
+            parse(&[]).unwrap().config_path,
+            PathBuf::from("bot_config.toml")
+        );
+        assert_eq!(
+            parse(&["--config", "/etc/bot.toml"]).unwrap().config_path,
+            PathBuf::from("/etc/bot.toml")
+        );
+        assert_eq!(
+            parse(&["--config=bot.toml"]).unwrap().config_path,
+            PathBuf::from("bot.toml")
+        );
+    }
+
+    #[test]
+    fn check_config() {
+        assert!(!parse(&[]).unwrap().check_config);
+
+        let args = parse(&["--check-config", "--config", "bot.toml"]).unwrap();
+        assert!(args.check_config);
+        assert_eq!(args.config_path, PathBuf::from("bot.toml"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+}
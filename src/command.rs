@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use crate::config::InvitePolicy;
+
+/// Command written after the bot username, such as `@bot invite stream`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Command<'a> {
+    /// Request an invite, with the name of the invite policy if any.
+    Invite {
+        policy: Option<&'a str>,
+    },
+    Help,
+    /// Show the pending request or the valid invite of the user.
+    Status,
+    /// Cancel the pending request and revoke the outstanding invites of the user.
+    Cancel,
+    /// Revoke the invites of the user, only for admins.
+    Revoke {
+        target: Option<&'a str>,
+    },
+    Unknown(&'a str),
+}
+
+/// Parse the text after the bot username.
+/// The bare mention, and the policy name without `invite` are also invite requests as before.
+/// The language keyword such as `lang:ja` is skipped.
+pub fn parse_command<'a>(
+    args: &'a str,
+    invite_policies: &BTreeMap<String, InvitePolicy>,
+) -> Command<'a> {
+    let mut words = args
+        .split_whitespace()
+        .filter(|word| !word.starts_with("lang:"));
+    let Some(word) = words.next() else {
+        return Command::Invite { policy: None };
+    };
+
+    match word.to_lowercase().as_str() {
+        "invite" => Command::Invite {
+            policy: words
+                .next()
+                .filter(|name| invite_policies.contains_key(*name)),
+        },
+        "help" => Command::Help,
+        "status" => Command::Status,
+        "cancel" => Command::Cancel,
+        "revoke" => Command::Revoke {
+            target: words.next(),
+        },
+        _ if invite_policies.contains_key(word) => Command::Invite { policy: Some(word) },
+        _ => Command::Unknown(word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let policies = BTreeMap::from([("stream".to_string(), InvitePolicy::default())]);
+
+        assert_eq!(
+            parse_command("", &policies),
+            Command::Invite { policy: None }
+        );
+        assert_eq!(
+            parse_command(" invite", &policies),
+            Command::Invite { policy: None }
+        );
+        assert_eq!(
+            parse_command(" Invite stream", &policies),
+            Command::Invite {
+                policy: Some("stream")
+            }
+        );
+        assert_eq!(
+            parse_command(" invite please", &policies),
+            Command::Invite { policy: None }
+        );
+        assert_eq!(
+            parse_command(" stream", &policies),
+            Command::Invite {
+                policy: Some("stream")
+            }
+        );
+        assert_eq!(
+            parse_command(" lang:ja invite stream", &policies),
+            Command::Invite {
+                policy: Some("stream")
+            }
+        );
+        assert_eq!(parse_command("\nhelp", &policies), Command::Help);
+        assert_eq!(parse_command(" status", &policies), Command::Status);
+        assert_eq!(parse_command(" cancel", &policies), Command::Cancel);
+        assert_eq!(
+            parse_command(" revoke @user@example.com", &policies),
+            Command::Revoke {
+                target: Some("@user@example.com")
+            }
+        );
+        assert_eq!(
+            parse_command(" hello", &policies),
+            Command::Unknown("hello")
+        );
+    }
+}
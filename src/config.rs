@@ -1,52 +1,1152 @@
-use std::fs::read_to_string;
+use std::{
+    collections::BTreeMap, env, fmt::Display, fs::read_to_string, io, num::NonZeroU32, path::Path,
+};
 
-use serde::Deserialize;
+use serde::{de::Error, Deserialize, Deserializer};
+use toml::{Table, Value};
 
-#[derive(PartialEq, Eq, Debug, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
 pub struct Config {
     pub misskey_host: String,
+    /// URL of the Misskey server such as `http://localhost:3000`, if it is not `https://{misskey_host}`.
+    pub misskey_api_url: Option<String>,
     pub misskey_bot_username: String,
     pub misskey_bot_token: String,
     pub discord_bot_token: String,
     pub discord_channel_invite: u64,
     pub discord_activity_watching: String,
+    pub discord_member: Option<DiscordMemberConfig>,
+    /// `{url}` and `{expires_at}` are replaced with the invite. The url is appended if `{url}` is not in the message.
     pub bot_reply_message_ok_invite: String,
     pub bot_reply_message_err_remote_user: String,
+    pub storage_path: String,
+    pub access_list: Option<AccessListConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub eligibility: Option<EligibilityConfig>,
+    pub roles: Option<RolesConfig>,
+    pub relation: Option<RelationConfig>,
+    /// Ask moderators before issuing invites.
+    pub approval: Option<ApprovalConfig>,
+    pub revocation: Option<RevocationConfig>,
+    /// Replies to `help`, `status` and `cancel`. Without this, every mention is an invite request.
+    pub commands: Option<CommandsConfig>,
+    /// Translations of the reply messages by language, such as `[messages.ja]`.
+    /// Keys are the names of the messages in this config, such as `"rate_limit.message"`.
+    #[serde(default)]
+    pub messages: BTreeMap<String, BTreeMap<String, String>>,
+    /// Parameters of invites, used if the request does not name a policy.
+    #[serde(default)]
+    pub invite: InvitePolicy,
+    /// Named invite parameters and destinations, chosen by `@bot invite <name>`,
+    /// or by `hosts` and `roles` of the policy if the request does not name one.
+    #[serde(default)]
+    pub invite_policies: BTreeMap<String, InvitePolicy>,
+    /// Other Misskey accounts served by this process, sharing the Discord bot.
+    #[serde(default)]
+    pub misskey_accounts: Vec<MisskeyAccountConfig>,
 }
 
-pub fn load_config() -> Config {
-    let config = read_to_string("bot_config.toml").unwrap();
-    parse_config(&config)
+impl Config {
+    /// Configs for each Misskey account, starting with the top level one.
+    pub fn accounts(&self) -> Vec<Config> {
+        let mut accounts = vec![self.clone()];
+
+        for account in &self.misskey_accounts {
+            let mut config = self.clone();
+            config.misskey_accounts = Vec::new();
+
+            config.misskey_host = account.misskey_host.clone();
+            config.misskey_api_url = account.misskey_api_url.clone();
+            config.misskey_bot_username = account.misskey_bot_username.clone();
+            config.misskey_bot_token = account.misskey_bot_token.clone();
+            if let Some(channel) = account.discord_channel_invite {
+                config.discord_channel_invite = channel;
+            }
+            if let Some(message) = &account.bot_reply_message_ok_invite {
+                config.bot_reply_message_ok_invite = message.clone();
+            }
+            if let Some(message) = &account.bot_reply_message_err_remote_user {
+                config.bot_reply_message_err_remote_user = message.clone();
+            }
+            if let Some(member) = &account.discord_member {
+                config.discord_member = Some(member.clone());
+            }
+            // User and role ids of the top level host mean nothing on another one.
+            let same_host = account
+                .misskey_host
+                .eq_ignore_ascii_case(&self.misskey_host);
+            config.access_list = account
+                .access_list
+                .clone()
+                .or(config.access_list.filter(|_| same_host));
+            config.roles = account.roles.clone().or(config.roles.filter(|_| same_host));
+            config.approval = account
+                .approval
+                .clone()
+                .or(config.approval.filter(|_| same_host));
+            config.revocation = account
+                .revocation
+                .clone()
+                .or(config.revocation.filter(|_| same_host));
+            for (lang, translation) in &account.messages {
+                config
+                    .messages
+                    .entry(lang.clone())
+                    .or_default()
+                    .extend(translation.clone());
+            }
+
+            accounts.push(config);
+        }
+
+        accounts
+    }
+
+    /// The bot account such as `@bot@example.com`, which the records in the storage belong to.
+    pub fn misskey_account(&self) -> String {
+        format!("{}@{}", self.misskey_bot_username, self.misskey_host)
+    }
+
+    /// Base URL of the Misskey server, without the trailing slash.
+    pub fn misskey_base_url(&self) -> String {
+        match &self.misskey_api_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("https://{}", self.misskey_host),
+        }
+    }
+}
+
+/// Another Misskey account, such as `[[misskey_accounts]]`. Omitted fields are the same as the top level.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct MisskeyAccountConfig {
+    pub misskey_host: String,
+    /// Not the same as the top level, but `https://{misskey_host}` if omitted.
+    pub misskey_api_url: Option<String>,
+    pub misskey_bot_username: String,
+    pub misskey_bot_token: String,
+    pub discord_channel_invite: Option<u64>,
+    pub bot_reply_message_ok_invite: Option<String>,
+    pub bot_reply_message_err_remote_user: Option<String>,
+    /// Replaces the top level one, such as for `discord_channel_invite` in another guild.
+    pub discord_member: Option<DiscordMemberConfig>,
+    /// These replace the top level ones, which have user and role ids, and
+    /// are inherited only by the accounts on the same `misskey_host`.
+    pub access_list: Option<AccessListConfig>,
+    pub roles: Option<RolesConfig>,
+    pub approval: Option<ApprovalConfig>,
+    /// `check_interval_minutes` of the top level one is used for every account.
+    pub revocation: Option<RevocationConfig>,
+    /// Translations added to the top level ones.
+    #[serde(default)]
+    pub messages: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Requirements for the requester's account.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct EligibilityConfig {
+    #[serde(default)]
+    pub min_account_age_hours: u32,
+    #[serde(default)]
+    pub min_notes_count: u64,
+    #[serde(default)]
+    pub min_followers_count: u64,
+    /// `{eligible_at}` is replaced with the time when the account becomes old enough.
+    pub message_account_too_new: String,
+    /// `{required}` is replaced with `min_notes_count`.
+    pub message_too_few_notes: String,
+    /// `{required}` is replaced with `min_followers_count`.
+    pub message_too_few_followers: String,
+}
+
+/// Requirements for the requester's Misskey roles. Roles are written as the role id or the name.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct RolesConfig {
+    /// The requester must have one of the roles, if not empty.
+    #[serde(default)]
+    pub require_any: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Reject silenced or suspended users.
+    #[serde(default)]
+    pub deny_restricted: bool,
+    pub message_missing_role: String,
+    pub message_denied_role: String,
+    pub message_restricted: String,
+}
+
+/// Requirement for the follow relation between the bot and the requester.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct RelationConfig {
+    pub require: RelationRequirement,
+    pub message: String,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationRequirement {
+    /// The requester follows the bot.
+    FollowingBot,
+    /// The bot follows the requester, which is vetted manually.
+    FollowedByBot,
+    Either,
+    Both,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct ApprovalConfig {
+    /// Misskey user ids who receive notices and decide requests by replying `approve` or `deny`.
+    pub moderator_user_ids: Vec<String>,
+    #[serde(default)]
+    pub approve_reactions: Vec<String>,
+    #[serde(default)]
+    pub deny_reactions: Vec<String>,
+    /// Notice to moderators. `{username}`, `{user_id}` and `{text}` are replaced with the request.
+    pub message_notice: String,
+    pub message_pending: String,
+    pub message_denied: String,
+}
+
+/// Revoke outstanding invites when the user gets suspended or deleted, or an admin asks by `@bot revoke @username`.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct RevocationConfig {
+    pub admin_user_ids: Vec<String>,
+    /// Interval to check the users who have outstanding invites.
+    pub check_interval_minutes: NonZeroU32,
+    /// Reply to the admin. `{count}` and `{target}` are replaced with the number of revoked invites and the user.
+    pub message_revoked: String,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct CommandsConfig {
+    /// Reply to `help`, and to unknown commands.
+    pub message_help: String,
+    pub message_status_pending: String,
+    /// `{url}` and `{expires_at}` are replaced with the valid invite. `{expires_at}` is empty if it never expires.
+    pub message_status_valid: String,
+    pub message_status_none: String,
+    /// `{count}` is replaced with the number of cancelled requests and revoked invites.
+    pub message_cancelled: String,
+    pub message_nothing_to_cancel: String,
+}
+
+/// Parameters of the invite, see <https://discord.com/developers/docs/resources/channel#create-channel-invite>.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvitePolicy {
+    /// Seconds until the invite expires, 0 for never.
+    #[serde(deserialize_with = "at_most::<_, 604800>")]
+    pub max_age: u32,
+    /// Max number of uses, 0 for unlimited.
+    #[serde(deserialize_with = "at_most::<_, 100>")]
+    pub max_uses: u32,
+    /// Grant temporary membership.
+    pub temporary: bool,
+    pub unique: bool,
+    pub target: Option<InviteTarget>,
+    /// Channel to create the invite in, `discord_channel_invite` by default.
+    pub channel_id: Option<u64>,
+    /// Reply with the invite, `bot_reply_message_ok_invite` by default.
+    /// Translated with the key `invite_policies.<name>.message_ok_invite`.
+    pub message_ok_invite: Option<String>,
+    /// Reply to `status` with the valid invite, `[commands] message_status_valid` by default.
+    /// Translated with the key `invite_policies.<name>.message_status_valid`.
+    pub message_status_valid: Option<String>,
+    /// Role given to the member who joined with the invite, instead of `[discord_member] role_id`.
+    /// The role of `[discord_member]` is not given with `channel_id`, which may be in another guild.
+    pub role_id: Option<u64>,
+    /// Nickname of the member who joined with the invite, instead of `[discord_member] nickname`.
+    pub nickname: Option<String>,
+    /// Choose this named policy for the users of these instances, including the local one.
+    pub hosts: Vec<String>,
+    /// Choose this named policy for the users who have one of these Misskey roles, written as the role id or the name.
+    pub roles: Vec<String>,
+}
+
+impl Default for InvitePolicy {
+    fn default() -> Self {
+        InvitePolicy {
+            max_age: 3600, // 1 hour
+            max_uses: 1,
+            temporary: false,
+            unique: true,
+            target: None,
+            channel_id: None,
+            message_ok_invite: None,
+            message_status_valid: None,
+            role_id: None,
+            nickname: None,
+            hosts: Vec::new(),
+            roles: Vec::new(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InviteTarget {
+    Stream { user_id: u64 },
+    EmbeddedApplication { application_id: u64 },
+}
+
+/// Settings for the Discord member who joined with the invite issued by the bot.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct DiscordMemberConfig {
+    /// Role given to the member.
+    pub role_id: Option<u64>,
+    /// Nickname of the member. `{username}` and `{host}` are replaced with the Misskey account.
+    pub nickname: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct AccessListConfig {
+    /// Path to the allow and deny lists, see `access_list-template.toml`.
+    pub path: String,
+    pub message_denied_user: String,
+    pub message_denied_host: String,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Reply to the rejected user. `{retry_after}` is replaced with the time when the user can request again.
+    pub message: String,
+    pub rules: Vec<RateLimitRule>,
+}
+
+/// Allow at most `max_invites` invites in any `period_hours` hours.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct RateLimitRule {
+    pub period_hours: NonZeroU32,
+    pub max_invites: NonZeroU32,
+}
+
+/// Prefix of the environment variables which override the config,
+/// such as `DISCORD_INVITER_MISSKEY_HOST` or `DISCORD_INVITER_RATE_LIMIT__MESSAGE` for `[rate_limit] message`.
+const ENV_PREFIX: &str = "DISCORD_INVITER_";
+
+/// Fields which can be read from the file at `<field>_file`, such as systemd credentials or Docker secrets.
+const SECRET_FIELDS: &[&str] = &["misskey_bot_token", "discord_bot_token"];
+
+/// Keys of the string fields in any table, besides the secret ones and the messages, such as
+/// `nickname` of `[discord_member]` and the policies. Environment variables for them are strings,
+/// even if they are valid TOML such as `123` or `true`.
+const STRING_FIELDS: &[&str] = &[
+    "misskey_host",
+    "misskey_api_url",
+    "misskey_bot_username",
+    "discord_activity_watching",
+    "storage_path",
+    "nickname",
+    "path",
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Parse(toml::de::Error),
+    /// The environment variable goes into the field which is not a table,
+    /// or into the element of an array which does not exist.
+    Env {
+        name: String,
+    },
+    SecretFile {
+        field: String,
+        err: io::Error,
+    },
+    /// The value is parsed but not usable.
+    Invalid {
+        field: String,
+        reason: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(err) => write!(f, "Cannot read the config: {}", err),
+            ConfigError::Parse(err) => write!(f, "Cannot parse the config: {}", err),
+            ConfigError::Env { name } => {
+                write!(
+                    f,
+                    "{} overrides the field which is not a table, or a missing element of an array",
+                    name
+                )
+            }
+            ConfigError::SecretFile { field, err } => {
+                write!(f, "Cannot read {}_file: {}", field, err)
+            }
+            ConfigError::Invalid { field, reason } => write!(f, "{} {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+pub fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    let config = read_to_string(path).map_err(ConfigError::Read)?;
+    parse_config(&config, env::vars())
+}
+
+fn parse_config(
+    config: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config, ConfigError> {
+    let mut table: Table = toml::from_str(config)?;
+
+    apply_env_overrides(&mut table, vars)?;
+    read_secret_files(&mut table)?;
+
+    let config: Config = Value::Table(table).try_into()?;
+    for (i, account) in config.accounts().iter().enumerate() {
+        validate(account).map_err(|err| match err {
+            ConfigError::Invalid { field, reason } if i > 0 => ConfigError::Invalid {
+                field: format!("misskey_accounts[{}].{}", i - 1, field),
+                reason,
+            },
+            err => err,
+        })?;
+    }
+
+    Ok(config)
+}
+
+/// Check the values which are parsed but not usable.
+fn validate(config: &Config) -> Result<(), ConfigError> {
+    let invalid = |field: &str, reason: &str| {
+        Err(ConfigError::Invalid {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        })
+    };
+
+    if config.misskey_host.is_empty() || config.misskey_host.contains(['/', ':']) {
+        return invalid(
+            "misskey_host",
+            "must be a host name such as `misskey.io`, without a scheme, a port or a path",
+        );
+    }
+    if let Some(url) = &config.misskey_api_url {
+        let is_base_url = reqwest::Url::parse(url).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https")
+                && url.has_host()
+                && url.query().is_none()
+                && url.fragment().is_none()
+        });
+        if !is_base_url {
+            return invalid(
+                "misskey_api_url",
+                "must be an http or https URL such as `http://localhost:3000`",
+            );
+        }
+    }
+    if !config.misskey_bot_username.starts_with('@') || config.misskey_bot_username.len() < 2 {
+        return invalid(
+            "misskey_bot_username",
+            "must start with `@`, such as `@bot`",
+        );
+    }
+    if config.misskey_bot_token.is_empty() {
+        return invalid("misskey_bot_token", "must not be empty");
+    }
+    if config.discord_bot_token.is_empty() {
+        return invalid("discord_bot_token", "must not be empty");
+    }
+    if config.discord_channel_invite == 0 {
+        return invalid("discord_channel_invite", "must be the channel id, not 0");
+    }
+    if let Some(DiscordMemberConfig {
+        role_id: Some(0), ..
+    }) = config.discord_member
+    {
+        return invalid("discord_member.role_id", "must be the role id, not 0");
+    }
+    if !config.invite.hosts.is_empty() || !config.invite.roles.is_empty() {
+        return invalid(
+            "invite",
+            "is the default policy, and must not have hosts or roles",
+        );
+    }
+    let policies = std::iter::once(("invite".to_string(), &config.invite)).chain(
+        config
+            .invite_policies
+            .iter()
+            .map(|(name, policy)| (format!("invite_policies.{}", name), policy)),
+    );
+    for (name, policy) in policies {
+        if policy.channel_id == Some(0) {
+            return invalid(
+                &format!("{}.channel_id", name),
+                "must be the channel id, not 0",
+            );
+        }
+        if policy.role_id == Some(0) {
+            return invalid(&format!("{}.role_id", name), "must be the role id, not 0");
+        }
+    }
+    if let Some(rate_limit) = &config.rate_limit {
+        if rate_limit.rules.is_empty() {
+            return invalid("rate_limit.rules", "must have at least one rule");
+        }
+    }
+    if let Some(approval) = &config.approval {
+        if approval.moderator_user_ids.is_empty() {
+            return invalid("approval.moderator_user_ids", "must not be empty");
+        }
+    }
+    for (i, account) in config.misskey_accounts.iter().enumerate() {
+        if account
+            .misskey_host
+            .eq_ignore_ascii_case(&config.misskey_host)
+        {
+            continue;
+        }
+        let not_inherited = [
+            (
+                "access_list",
+                config.access_list.is_some() && account.access_list.is_none(),
+            ),
+            ("roles", config.roles.is_some() && account.roles.is_none()),
+            (
+                "approval",
+                config.approval.is_some() && account.approval.is_none(),
+            ),
+            (
+                "revocation",
+                config.revocation.is_some() && account.revocation.is_none(),
+            ),
+        ];
+        if let Some((section, _)) = not_inherited.iter().find(|(_, missing)| *missing) {
+            return invalid(
+                &format!("misskey_accounts[{}].{}", i, section),
+                "must be set for another misskey_host, as the ids in the top level one are not inherited",
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Override the fields by `DISCORD_INVITER_*` variables. Nested tables are separated by `__`,
+/// and the existing elements of arrays of tables are chosen by the index, such as `MISSKEY_ACCOUNTS__0__MISSKEY_HOST`.
+/// Values are read as TOML, or as a string if the field is a string or the value is not valid TOML.
+fn apply_env_overrides(
+    table: &mut Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), ConfigError> {
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        let Some((key, tables)) = path.split_last() else {
+            continue;
+        };
+
+        let mut target = &mut *table;
+        let mut tables = tables.iter();
+        while let Some(table_name) = tables.next() {
+            let mut value = target
+                .entry(table_name.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            while let Value::Array(array) = value {
+                let index = tables.next().and_then(|index| index.parse::<usize>().ok());
+                let Some(element) = index.and_then(|index| array.get_mut(index)) else {
+                    return Err(ConfigError::Env { name });
+                };
+                value = element;
+            }
+            let Value::Table(next) = value else {
+                return Err(ConfigError::Env { name });
+            };
+            target = next;
+        }
+
+        let value = match target.get(key) {
+            Some(Value::String(_)) => Value::String(raw),
+            _ if is_string_field(&path) => Value::String(raw),
+            _ => parse_value(&raw).unwrap_or(Value::String(raw)),
+        };
+        target.insert(key.clone(), value);
+    }
+
+    Ok(())
+}
+
+fn is_string_field(path: &[String]) -> bool {
+    let key = path.last().map(String::as_str).unwrap_or_default();
+    let is_secret = SECRET_FIELDS
+        .iter()
+        .any(|field| key == *field || key == format!("{}_file", field));
+
+    is_secret
+        || key.starts_with("message")
+        || key.starts_with("bot_reply_message")
+        || path.iter().any(|table| table == "messages")
+        || STRING_FIELDS.contains(&key)
+}
+
+fn parse_value(raw: &str) -> Option<Value> {
+    let mut table: Table = toml::from_str(&format!("value = {}", raw)).ok()?;
+    table.remove("value")
 }
 
-fn parse_config(config: &str) -> Config {
-    toml::from_str(config).unwrap()
+/// Replace `<field>_file` with the content of the file, without the trailing newline.
+fn read_secret_files(table: &mut Table) -> Result<(), ConfigError> {
+    if let Some(Value::Array(accounts)) = table.get_mut("misskey_accounts") {
+        for account in accounts {
+            if let Value::Table(account) = account {
+                read_secret_files(account)?;
+            }
+        }
+    }
+
+    for field in SECRET_FIELDS {
+        let Some(path) = table.remove(&format!("{}_file", field)) else {
+            continue;
+        };
+        let Value::String(path) = path else {
+            return Err(ConfigError::Invalid {
+                field: format!("{}_file", field),
+                reason: "must be a path".to_string(),
+            });
+        };
+
+        let secret = read_to_string(&path).map_err(|err| ConfigError::SecretFile {
+            field: field.to_string(),
+            err,
+        })?;
+        let secret = secret.trim_end_matches(['\r', '\n']).to_string();
+        table.insert(field.to_string(), Value::String(secret));
+    }
+
+    Ok(())
+}
+
+fn at_most<'de, D, const MAX: u32>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = u32::deserialize(deserializer)?;
+    if value > MAX {
+        return Err(D::Error::custom(format!(
+            "{} is out of range, must be at most {}",
+            value, MAX
+        )));
+    }
+    Ok(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_fixtures::full_config_text;
 
     #[test]
     fn valid_config_all() {
         assert_eq!(
-            parse_config(&read_to_string("bot_config-template.toml").unwrap()),
+            parse_config(&full_config_text(), []).unwrap(),
             Config {
                 misskey_host: "example.com".to_string(),
+                misskey_api_url: None,
                 misskey_bot_username: "@test".to_string(),
                 misskey_bot_token: "misskey-token".to_string(),
                 discord_bot_token: "discord-token".to_string(),
                 discord_channel_invite: 1234,
                 discord_activity_watching: "discord_activity_watching".to_string(),
+                discord_member: Some(DiscordMemberConfig {
+                    role_id: Some(5678),
+                    nickname: Some("@{username}@{host}".to_string()),
+                }),
                 bot_reply_message_ok_invite: "bot_reply_message_ok_invite".to_string(),
                 bot_reply_message_err_remote_user: "bot_reply_message_err_remote_user".to_string(),
+                storage_path: "bot_data.sqlite3".to_string(),
+                access_list: Some(AccessListConfig {
+                    path: "access_list.toml".to_string(),
+                    message_denied_user: "access_list.message_denied_user".to_string(),
+                    message_denied_host: "access_list.message_denied_host".to_string(),
+                }),
+                rate_limit: Some(RateLimitConfig {
+                    message: "rate_limit.message {retry_after}".to_string(),
+                    rules: vec![
+                        RateLimitRule {
+                            period_hours: NonZeroU32::new(24).unwrap(),
+                            max_invites: NonZeroU32::new(1).unwrap(),
+                        },
+                        RateLimitRule {
+                            period_hours: NonZeroU32::new(168).unwrap(),
+                            max_invites: NonZeroU32::new(3).unwrap(),
+                        },
+                    ],
+                }),
+                eligibility: Some(EligibilityConfig {
+                    min_account_age_hours: 72,
+                    min_notes_count: 10,
+                    min_followers_count: 0,
+                    message_account_too_new: "eligibility.message_account_too_new {eligible_at}"
+                        .to_string(),
+                    message_too_few_notes: "eligibility.message_too_few_notes {required}"
+                        .to_string(),
+                    message_too_few_followers: "eligibility.message_too_few_followers {required}"
+                        .to_string(),
+                }),
+                roles: Some(RolesConfig {
+                    require_any: vec!["verified".to_string()],
+                    deny: vec!["role-id".to_string()],
+                    deny_restricted: true,
+                    message_missing_role: "roles.message_missing_role".to_string(),
+                    message_denied_role: "roles.message_denied_role".to_string(),
+                    message_restricted: "roles.message_restricted".to_string(),
+                }),
+                relation: Some(RelationConfig {
+                    require: RelationRequirement::Either,
+                    message: "relation.message".to_string(),
+                }),
+                approval: Some(ApprovalConfig {
+                    moderator_user_ids: vec!["moderator-id".to_string()],
+                    approve_reactions: vec!["👍".to_string()],
+                    deny_reactions: vec!["👎".to_string()],
+                    message_notice: "approval.message_notice @{username} ({user_id}) {text}"
+                        .to_string(),
+                    message_pending: "approval.message_pending".to_string(),
+                    message_denied: "approval.message_denied".to_string(),
+                }),
+                revocation: Some(RevocationConfig {
+                    admin_user_ids: vec!["admin-id".to_string()],
+                    check_interval_minutes: NonZeroU32::new(60).unwrap(),
+                    message_revoked: "revocation.message_revoked {count} {target}".to_string(),
+                }),
+                commands: Some(CommandsConfig {
+                    message_help: "commands.message_help".to_string(),
+                    message_status_pending: "commands.message_status_pending".to_string(),
+                    message_status_valid: "commands.message_status_valid {url} {expires_at}"
+                        .to_string(),
+                    message_status_none: "commands.message_status_none".to_string(),
+                    message_cancelled: "commands.message_cancelled {count}".to_string(),
+                    message_nothing_to_cancel: "commands.message_nothing_to_cancel".to_string(),
+                }),
+                messages: BTreeMap::from([(
+                    "ja".to_string(),
+                    BTreeMap::from([
+                        (
+                            "bot_reply_message_ok_invite".to_string(),
+                            "messages.ja.bot_reply_message_ok_invite {url}".to_string(),
+                        ),
+                        (
+                            "rate_limit.message".to_string(),
+                            "messages.ja.rate_limit.message {retry_after}".to_string(),
+                        ),
+                    ]),
+                )]),
+                invite: InvitePolicy {
+                    max_age: 3600,
+                    max_uses: 1,
+                    temporary: false,
+                    unique: true,
+                    target: None,
+                    channel_id: None,
+                    message_ok_invite: None,
+                    message_status_valid: None,
+                    role_id: None,
+                    nickname: None,
+                    hosts: vec![],
+                    roles: vec![],
+                },
+                invite_policies: BTreeMap::from([
+                    (
+                        "gaming".to_string(),
+                        InvitePolicy {
+                            channel_id: Some(9012),
+                            message_ok_invite: Some(
+                                "invite_policies.gaming.message_ok_invite {url}".to_string()
+                            ),
+                            message_status_valid: Some(
+                                "invite_policies.gaming.message_status_valid {url}".to_string()
+                            ),
+                            role_id: Some(3456),
+                            nickname: Some("{username}@{host}".to_string()),
+                            hosts: vec!["game.example.com".to_string()],
+                            roles: vec!["gamer".to_string()],
+                            ..InvitePolicy::default()
+                        }
+                    ),
+                    (
+                        "stream".to_string(),
+                        InvitePolicy {
+                            max_age: 600,
+                            max_uses: 5,
+                            temporary: true,
+                            unique: true,
+                            target: Some(InviteTarget::Stream { user_id: 5678 }),
+                            channel_id: None,
+                            message_ok_invite: None,
+                            message_status_valid: None,
+                            role_id: None,
+                            nickname: None,
+                            hosts: vec![],
+                            roles: vec![],
+                        }
+                    ),
+                ]),
+                misskey_accounts: vec![MisskeyAccountConfig {
+                    misskey_host: "other.example.com".to_string(),
+                    misskey_api_url: None,
+                    misskey_bot_username: "@other".to_string(),
+                    misskey_bot_token: "other-misskey-token".to_string(),
+                    discord_channel_invite: Some(5678),
+                    bot_reply_message_ok_invite: Some(
+                        "misskey_accounts.bot_reply_message_ok_invite".to_string()
+                    ),
+                    bot_reply_message_err_remote_user: None,
+                    discord_member: Some(DiscordMemberConfig {
+                        role_id: Some(7890),
+                        nickname: None,
+                    }),
+                    access_list: Some(AccessListConfig {
+                        path: "access_list-other.toml".to_string(),
+                        message_denied_user: "misskey_accounts.access_list.message_denied_user"
+                            .to_string(),
+                        message_denied_host: "misskey_accounts.access_list.message_denied_host"
+                            .to_string(),
+                    }),
+                    roles: Some(RolesConfig {
+                        require_any: vec!["other-verified".to_string()],
+                        deny: vec![],
+                        deny_restricted: true,
+                        message_missing_role: "misskey_accounts.roles.message_missing_role"
+                            .to_string(),
+                        message_denied_role: "misskey_accounts.roles.message_denied_role"
+                            .to_string(),
+                        message_restricted: "misskey_accounts.roles.message_restricted".to_string(),
+                    }),
+                    approval: Some(ApprovalConfig {
+                        moderator_user_ids: vec!["other-moderator-id".to_string()],
+                        approve_reactions: vec![],
+                        deny_reactions: vec![],
+                        message_notice: "misskey_accounts.approval.message_notice".to_string(),
+                        message_pending: "misskey_accounts.approval.message_pending".to_string(),
+                        message_denied: "misskey_accounts.approval.message_denied".to_string(),
+                    }),
+                    revocation: Some(RevocationConfig {
+                        admin_user_ids: vec!["other-admin-id".to_string()],
+                        check_interval_minutes: NonZeroU32::new(60).unwrap(),
+                        message_revoked: "misskey_accounts.revocation.message_revoked".to_string(),
+                    }),
+                    messages: BTreeMap::from([(
+                        "ja".to_string(),
+                        BTreeMap::from([(
+                            "bot_reply_message_err_remote_user".to_string(),
+                            "misskey_accounts.messages.ja.bot_reply_message_err_remote_user"
+                                .to_string(),
+                        )]),
+                    )]),
+                }],
             }
         );
     }
 
     #[test]
-    #[should_panic]
+    fn valid_config_template() {
+        let config =
+            parse_config(&read_to_string("bot_config-template.toml").unwrap(), []).unwrap();
+
+        assert_eq!(config.misskey_bot_username, "@bot");
+        assert_eq!(config.access_list, None);
+        assert_eq!(config.approval, None);
+        assert_eq!(config.roles, None);
+        assert!(config.misskey_accounts.is_empty());
+    }
+
+    #[test]
+    fn accounts() {
+        let config = parse_config(&full_config_text(), []).unwrap();
+        let accounts = config.accounts();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0], config);
+
+        let other = &accounts[1];
+        assert_eq!(other.misskey_host, "other.example.com");
+        assert_eq!(other.misskey_bot_username, "@other");
+        assert_eq!(other.discord_channel_invite, 5678);
+        assert_eq!(
+            other.bot_reply_message_ok_invite,
+            "misskey_accounts.bot_reply_message_ok_invite"
+        );
+        assert_eq!(
+            other.bot_reply_message_err_remote_user,
+            config.bot_reply_message_err_remote_user
+        );
+        assert_eq!(other.messages["ja"].len(), 3);
+        assert_eq!(other.discord_member.as_ref().unwrap().role_id, Some(7890));
+        assert_eq!(other.misskey_base_url(), "https://other.example.com");
+        assert_eq!(other.discord_bot_token, config.discord_bot_token);
+        assert!(other.misskey_accounts.is_empty());
+
+        // Sections with ids are replaced, and inherited only on the same host.
+        let own = &config.misskey_accounts[0];
+        assert_eq!(other.access_list, own.access_list);
+        assert_eq!(other.roles, own.roles);
+        assert_eq!(other.approval, own.approval);
+        assert_eq!(other.revocation, own.revocation);
+        assert_eq!(other.eligibility, config.eligibility);
+
+        let mut config = config.clone();
+        config.misskey_accounts[0].misskey_host = "EXAMPLE.com".to_string();
+        config.misskey_accounts[0].approval = None;
+        assert_eq!(config.accounts()[1].approval, config.approval);
+        config.misskey_accounts[0].misskey_host = "other.example.com".to_string();
+        assert_eq!(config.accounts()[1].approval, None);
+    }
+
+    #[test]
+    fn invalid_rate_limit_zero() {
+        let config = full_config_text().replace("max_invites = 1", "max_invites = 0");
+        assert!(matches!(
+            parse_config(&config, []),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn valid_config_without_invite() {
+        let config = full_config_text();
+        let config = &config[..config.find("[invite]").unwrap()];
+
+        assert_eq!(
+            parse_config(config, []).unwrap().invite,
+            InvitePolicy::default()
+        );
+    }
+
+    #[test]
+    fn invalid_invite_max_age() {
+        let config = full_config_text().replace("max_age = 3600", "max_age = 604801");
+        assert!(matches!(
+            parse_config(&config, []),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_invite_target() {
+        let config = full_config_text().replace("user_id = 5678", "application_id = 5678");
+        assert!(matches!(
+            parse_config(&config, []),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
     fn invalid_nothing_all() {
-        parse_config("");
+        assert!(matches!(parse_config("", []), Err(ConfigError::Parse(_))));
+    }
+
+    fn invalid_field(from: &str, to: &str) -> String {
+        let config = full_config_text().replace(from, to);
+        match parse_config(&config, []) {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn base_url() {
+        let template = full_config_text();
+        let config = parse_config(&template, []).unwrap();
+        assert_eq!(config.misskey_base_url(), "https://example.com");
+
+        let config = parse_config(
+            &template.replace("# misskey_api_url", "misskey_api_url"),
+            [],
+        )
+        .unwrap();
+        assert_eq!(config.misskey_base_url(), "http://localhost:3000");
+    }
+
+    #[test]
+    fn invalid_semantics() {
+        assert_eq!(
+            invalid_field("\"example.com\"", "\"https://example.com\""),
+            "misskey_host"
+        );
+        assert_eq!(
+            invalid_field(
+                "# misskey_api_url = \"http://localhost:3000/\"",
+                "misskey_api_url = \"ws://localhost:3000\""
+            ),
+            "misskey_api_url"
+        );
+        assert_eq!(
+            invalid_field(
+                "misskey_bot_username = \"@test\"",
+                "misskey_bot_username = \"test\""
+            ),
+            "misskey_bot_username"
+        );
+        assert_eq!(
+            invalid_field(
+                "discord_channel_invite = 1234",
+                "discord_channel_invite = 0"
+            ),
+            "discord_channel_invite"
+        );
+        assert_eq!(
+            invalid_field(
+                "moderator_user_ids = [\"moderator-id\"]",
+                "moderator_user_ids = []"
+            ),
+            "approval.moderator_user_ids"
+        );
+        assert_eq!(
+            invalid_field("channel_id = 9012", "channel_id = 0"),
+            "invite_policies.gaming.channel_id"
+        );
+        assert_eq!(
+            invalid_field("[invite]", "[invite]\nhosts = [\"example.com\"]"),
+            "invite"
+        );
+        assert_eq!(
+            invalid_field(
+                "misskey_bot_username = \"@other\"",
+                "misskey_bot_username = \"other\""
+            ),
+            "misskey_accounts[0].misskey_bot_username"
+        );
+        assert_eq!(
+            invalid_field(
+                "[misskey_accounts.approval]",
+                "[misskey_accounts.other_approval]"
+            ),
+            "misskey_accounts[0].approval"
+        );
+    }
+
+    #[test]
+    fn invalid_env_override() {
+        let result = parse_config(
+            &full_config_text(),
+            [(
+                "DISCORD_INVITER_MISSKEY_HOST__PORT".to_string(),
+                "443".to_string(),
+            )],
+        );
+        assert!(matches!(result, Err(ConfigError::Env { .. })));
+
+        for name in [
+            "DISCORD_INVITER_MISSKEY_ACCOUNTS__1__MISSKEY_HOST",
+            "DISCORD_INVITER_MISSKEY_ACCOUNTS__FIRST__MISSKEY_HOST",
+        ] {
+            let result = parse_config(
+                &full_config_text(),
+                [(name.to_string(), "other.example.com".to_string())],
+            );
+            assert!(matches!(result, Err(ConfigError::Env { .. })), "{}", name);
+        }
+    }
+
+    #[test]
+    fn env_overrides_of_missing_strings() {
+        let config = full_config_text()
+            .replace("misskey_bot_token = \"misskey-token\"\n", "")
+            .replace("misskey_host = \"example.com\"\n", "");
+        let config = parse_config(
+            &config,
+            [
+                ("DISCORD_INVITER_MISSKEY_BOT_TOKEN", "123"),
+                ("DISCORD_INVITER_MISSKEY_HOST", "true"),
+                ("DISCORD_INVITER_MESSAGES__JA__RELATION.MESSAGE", "1.5"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(config.misskey_bot_token, "123");
+        assert_eq!(config.misskey_host, "true");
+        assert_eq!(config.messages["ja"]["relation.message"], "1.5");
+    }
+
+    #[test]
+    fn env_overrides() {
+        let config = parse_config(
+            &full_config_text(),
+            [
+                ("DISCORD_INVITER_MISSKEY_BOT_TOKEN", "1234"),
+                ("DISCORD_INVITER_DISCORD_CHANNEL_INVITE", "5678"),
+                ("DISCORD_INVITER_RATE_LIMIT__MESSAGE", "overridden"),
+                ("DISCORD_INVITER_INVITE_POLICIES__STREAM__MAX_USES", "10"),
+                ("DISCORD_INVITER_DISCORD_MEMBER__ROLE_ID", "42"),
+                ("OTHER_MISSKEY_HOST", "other.example.com"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(config.misskey_host, "example.com");
+        assert_eq!(config.misskey_bot_token, "1234");
+        assert_eq!(config.discord_channel_invite, 5678);
+        assert_eq!(config.rate_limit.unwrap().message, "overridden");
+        assert_eq!(config.invite_policies["stream"].max_uses, 10);
+        assert_eq!(config.discord_member.unwrap().role_id, Some(42));
+    }
+
+    #[test]
+    fn env_overrides_in_arrays_and_policies() {
+        let config = parse_config(
+            &full_config_text(),
+            [
+                ("DISCORD_INVITER_INVITE_POLICIES__GAMING__NICKNAME", "123"),
+                (
+                    "DISCORD_INVITER_MISSKEY_ACCOUNTS__0__MISSKEY_BOT_TOKEN",
+                    "1234",
+                ),
+                (
+                    "DISCORD_INVITER_MISSKEY_ACCOUNTS__0__DISCORD_MEMBER__NICKNAME",
+                    "true",
+                ),
+                (
+                    "DISCORD_INVITER_MISSKEY_ACCOUNTS__0__MESSAGES__JA__NOTE",
+                    "1",
+                ),
+                ("DISCORD_INVITER_RATE_LIMIT__RULES__1__MAX_INVITES", "5"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.invite_policies["gaming"].nickname.as_deref(),
+            Some("123")
+        );
+        let account = &config.misskey_accounts[0];
+        assert_eq!(account.misskey_bot_token, "1234");
+        assert_eq!(
+            account.discord_member.as_ref().unwrap().nickname.as_deref(),
+            Some("true")
+        );
+        assert_eq!(account.messages["ja"]["note"], "1");
+        assert_eq!(config.rate_limit.unwrap().rules[1].max_invites.get(), 5);
+    }
+
+    #[test]
+    fn secret_files() {
+        let path = env::temp_dir().join(format!(
+            "discord_inviter_misskey_secret_{}",
+            std::process::id()
+        ));
+        std::fs::write(&path, "secret-token\n").unwrap();
+
+        let config = full_config_text().replace(
+            "discord_bot_token = \"discord-token\"",
+            &format!("discord_bot_token_file = {:?}", path.to_str().unwrap()),
+        );
+        let config = parse_config(&config, []).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.discord_bot_token, "secret-token");
     }
 }
@@ -0,0 +1,291 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    api_misskey::{UserDetailed, UserRelation},
+    config::{EligibilityConfig, RelationRequirement, RolesConfig},
+    messages::Messages,
+    template::{format_time, render},
+};
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Ineligible {
+    AccountTooNew { eligible_at: DateTime<Utc> },
+    TooFewNotes { required: u64 },
+    TooFewFollowers { required: u64 },
+}
+
+impl Ineligible {
+    /// Reply message for the rejected user.
+    pub fn message(&self, config: &EligibilityConfig, messages: &Messages) -> String {
+        match self {
+            Ineligible::AccountTooNew { eligible_at } => render(
+                messages.get(
+                    "eligibility.message_account_too_new",
+                    &config.message_account_too_new,
+                ),
+                &[("eligible_at", &format_time(*eligible_at))],
+            ),
+            Ineligible::TooFewNotes { required } => render(
+                messages.get(
+                    "eligibility.message_too_few_notes",
+                    &config.message_too_few_notes,
+                ),
+                &[("required", &required.to_string())],
+            ),
+            Ineligible::TooFewFollowers { required } => render(
+                messages.get(
+                    "eligibility.message_too_few_followers",
+                    &config.message_too_few_followers,
+                ),
+                &[("required", &required.to_string())],
+            ),
+        }
+    }
+}
+
+/// Returns the first unmet requirement, or `None` if the user is eligible.
+pub fn check_eligibility(
+    config: &EligibilityConfig,
+    user: &UserDetailed,
+    now: DateTime<Utc>,
+) -> Option<Ineligible> {
+    let eligible_at = user.created_at + Duration::hours(config.min_account_age_hours.into());
+    if eligible_at > now {
+        return Some(Ineligible::AccountTooNew { eligible_at });
+    }
+
+    if user.notes_count < config.min_notes_count {
+        return Some(Ineligible::TooFewNotes {
+            required: config.min_notes_count,
+        });
+    }
+
+    if user.followers_count < config.min_followers_count {
+        return Some(Ineligible::TooFewFollowers {
+            required: config.min_followers_count,
+        });
+    }
+
+    None
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum RoleRejection {
+    /// The user is silenced or suspended.
+    Restricted,
+    DeniedRole {
+        role: String,
+    },
+    MissingRole,
+}
+
+impl RoleRejection {
+    pub fn message<'a>(&self, config: &'a RolesConfig, messages: &Messages<'a>) -> &'a str {
+        match self {
+            RoleRejection::Restricted => {
+                messages.get("roles.message_restricted", &config.message_restricted)
+            }
+            RoleRejection::DeniedRole { .. } => {
+                messages.get("roles.message_denied_role", &config.message_denied_role)
+            }
+            RoleRejection::MissingRole => {
+                messages.get("roles.message_missing_role", &config.message_missing_role)
+            }
+        }
+    }
+}
+
+pub fn check_roles(config: &RolesConfig, user: &UserDetailed) -> Option<RoleRejection> {
+    if config.deny_restricted && (user.is_silenced || user.is_suspended) {
+        return Some(RoleRejection::Restricted);
+    }
+
+    let has_role = |name: &String| {
+        user.roles
+            .iter()
+            .any(|role| role.id == *name || role.name == *name)
+    };
+
+    if let Some(role) = config.deny.iter().find(|name| has_role(name)) {
+        return Some(RoleRejection::DeniedRole { role: role.clone() });
+    }
+
+    if !config.require_any.is_empty() && !config.require_any.iter().any(has_role) {
+        return Some(RoleRejection::MissingRole);
+    }
+
+    None
+}
+
+pub fn is_relation_satisfied(require: RelationRequirement, relation: &UserRelation) -> bool {
+    match require {
+        RelationRequirement::FollowingBot => relation.is_followed,
+        RelationRequirement::FollowedByBot => relation.is_following,
+        RelationRequirement::Either => relation.is_followed || relation.is_following,
+        RelationRequirement::Both => relation.is_followed && relation.is_following,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::{check_eligibility, check_roles, is_relation_satisfied, Ineligible, RoleRejection};
+    use crate::{
+        api_misskey::{Role, UserDetailed, UserRelation},
+        config::{EligibilityConfig, RelationRequirement, RolesConfig},
+        messages::Messages,
+        test_fixtures::{hours, user},
+    };
+
+    fn config() -> EligibilityConfig {
+        EligibilityConfig {
+            min_account_age_hours: 24,
+            min_notes_count: 10,
+            min_followers_count: 1,
+            message_account_too_new: "{eligible_at}".to_string(),
+            message_too_few_notes: "{required}".to_string(),
+            message_too_few_followers: "{required}".to_string(),
+        }
+    }
+
+    fn account(created_at: DateTime<Utc>, notes_count: u64, followers_count: u64) -> UserDetailed {
+        UserDetailed {
+            created_at,
+            notes_count,
+            followers_count,
+            ..user(&[])
+        }
+    }
+
+    #[test]
+    fn eligible() {
+        assert_eq!(
+            check_eligibility(&config(), &account(hours(0), 10, 1), hours(24)),
+            None
+        );
+    }
+
+    #[test]
+    fn account_too_new() {
+        let result = check_eligibility(&config(), &account(hours(10), 10, 1), hours(24));
+        assert_eq!(
+            result,
+            Some(Ineligible::AccountTooNew {
+                eligible_at: hours(34)
+            })
+        );
+        assert_eq!(
+            result.unwrap().message(&config(), &Messages::default()),
+            "1970-01-02 10:00 UTC"
+        );
+    }
+
+    #[test]
+    fn too_few_notes_and_followers() {
+        assert_eq!(
+            check_eligibility(&config(), &account(hours(0), 9, 1), hours(24)),
+            Some(Ineligible::TooFewNotes { required: 10 })
+        );
+        assert_eq!(
+            check_eligibility(&config(), &account(hours(0), 10, 0), hours(24)),
+            Some(Ineligible::TooFewFollowers { required: 1 })
+        );
+    }
+
+    #[test]
+    fn roles() {
+        let config = RolesConfig {
+            require_any: vec!["verified".to_string(), "member-id".to_string()],
+            deny: vec!["spammer".to_string()],
+            deny_restricted: true,
+            message_missing_role: String::new(),
+            message_denied_role: String::new(),
+            message_restricted: String::new(),
+        };
+        let with_roles = |roles: &[(&str, &str)]| UserDetailed {
+            roles: roles
+                .iter()
+                .map(|(id, name)| Role {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                })
+                .collect(),
+            ..user(&[])
+        };
+
+        assert_eq!(
+            check_roles(&config, &with_roles(&[("verified-id", "verified")])),
+            None
+        );
+        assert_eq!(
+            check_roles(&config, &with_roles(&[("member-id", "member")])),
+            None
+        );
+        assert_eq!(
+            check_roles(&config, &with_roles(&[("other-id", "other")])),
+            Some(RoleRejection::MissingRole)
+        );
+        assert_eq!(
+            check_roles(
+                &config,
+                &with_roles(&[("verified-id", "verified"), ("spammer-id", "spammer")])
+            ),
+            Some(RoleRejection::DeniedRole {
+                role: "spammer".to_string()
+            })
+        );
+        assert_eq!(
+            check_roles(
+                &config,
+                &UserDetailed {
+                    is_silenced: true,
+                    ..with_roles(&[("verified-id", "verified")])
+                }
+            ),
+            Some(RoleRejection::Restricted)
+        );
+    }
+
+    #[test]
+    fn relation() {
+        fn relation(is_following: bool, is_followed: bool) -> UserRelation {
+            UserRelation {
+                id: "user".to_string(),
+                is_following,
+                is_followed,
+            }
+        }
+
+        let cases = [
+            (
+                RelationRequirement::FollowingBot,
+                [false, true, false, true],
+            ),
+            (
+                RelationRequirement::FollowedByBot,
+                [false, false, true, true],
+            ),
+            (RelationRequirement::Either, [false, true, true, true]),
+            (RelationRequirement::Both, [false, false, false, true]),
+        ];
+        for (require, expected) in cases {
+            // (bot follows user, user follows bot)
+            let relations = [
+                relation(false, false),
+                relation(false, true),
+                relation(true, false),
+                relation(true, true),
+            ];
+            for (relation, expected) in relations.iter().zip(expected) {
+                assert_eq!(
+                    is_relation_satisfied(require, relation),
+                    expected,
+                    "{:?} {:?}",
+                    require,
+                    relation
+                );
+            }
+        }
+    }
+}
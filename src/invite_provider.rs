@@ -0,0 +1,146 @@
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use serenity::async_trait;
+
+use crate::config::InvitePolicy;
+
+pub struct IssuedInvite {
+    pub code: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl IssuedInvite {
+    pub fn url(&self) -> String {
+        invite_url(&self.code)
+    }
+}
+
+pub fn invite_url(code: &str) -> String {
+    format!("https://discord.gg/{}", code)
+}
+
+/// Issuer of the invites, such as Discord.
+#[async_trait(?Send)]
+pub trait InviteProvider {
+    async fn generate_invite(
+        &self,
+        channel_id: u64,
+        policy: &InvitePolicy,
+        reason: &str,
+    ) -> Result<IssuedInvite, Box<dyn Error>>;
+
+    /// Whether the invite can still be used. Only for single-use invites,
+    /// because the invites used by some but not all are not told apart.
+    async fn is_invite_valid(&self, code: &str) -> Result<bool, Box<dyn Error>>;
+
+    /// Delete the invite. Returns `false` if it has been already deleted.
+    async fn delete_invite(&self, code: &str) -> Result<bool, Box<dyn Error>>;
+}
+
+#[cfg(test)]
+pub use fake::{FakeInvite, FakeInviteProvider};
+
+#[cfg(test)]
+mod fake {
+    use std::{error::Error, sync::Mutex};
+
+    use chrono::{Duration, Utc};
+    use serenity::async_trait;
+
+    use super::{InviteProvider, IssuedInvite};
+    use crate::config::InvitePolicy;
+
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub struct FakeInvite {
+        pub code: String,
+        pub channel_id: u64,
+        pub reason: String,
+        pub deleted: bool,
+    }
+
+    /// Invites kept in memory instead of Discord, with the codes `code1`, `code2` and so on.
+    #[derive(Default)]
+    pub struct FakeInviteProvider {
+        invites: Mutex<Vec<FakeInvite>>,
+    }
+
+    impl FakeInviteProvider {
+        pub fn invites(&self) -> Vec<FakeInvite> {
+            self.invites.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl InviteProvider for FakeInviteProvider {
+        async fn generate_invite(
+            &self,
+            channel_id: u64,
+            policy: &InvitePolicy,
+            reason: &str,
+        ) -> Result<IssuedInvite, Box<dyn Error>> {
+            let mut invites = self.invites.lock().unwrap();
+            let code = format!("code{}", invites.len() + 1);
+            invites.push(FakeInvite {
+                code: code.clone(),
+                channel_id,
+                reason: reason.to_string(),
+                deleted: false,
+            });
+
+            let expires_at =
+                (policy.max_age > 0).then(|| Utc::now() + Duration::seconds(policy.max_age.into()));
+            Ok(IssuedInvite { code, expires_at })
+        }
+
+        async fn is_invite_valid(&self, code: &str) -> Result<bool, Box<dyn Error>> {
+            let invites = self.invites.lock().unwrap();
+            Ok(invites
+                .iter()
+                .any(|invite| invite.code == code && !invite.deleted))
+        }
+
+        async fn delete_invite(&self, code: &str) -> Result<bool, Box<dyn Error>> {
+            let mut invites = self.invites.lock().unwrap();
+            let invite = invites
+                .iter_mut()
+                .find(|invite| invite.code == code && !invite.deleted);
+
+            Ok(invite.map(|invite| invite.deleted = true).is_some())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FakeInvite, FakeInviteProvider, InviteProvider};
+    use crate::config::InvitePolicy;
+
+    #[tokio::test]
+    async fn fake() {
+        let provider = FakeInviteProvider::default();
+
+        let invite = provider
+            .generate_invite(1234, &InvitePolicy::default(), "reason")
+            .await
+            .unwrap();
+        assert_eq!(invite.code, "code1");
+        assert!(invite.expires_at.is_some());
+        assert!(provider.is_invite_valid("code1").await.unwrap());
+
+        assert!(provider.delete_invite("code1").await.unwrap());
+        assert!(!provider.delete_invite("code1").await.unwrap());
+        assert!(!provider.is_invite_valid("code1").await.unwrap());
+        assert!(!provider.is_invite_valid("code2").await.unwrap());
+
+        assert_eq!(
+            provider.invites(),
+            [FakeInvite {
+                code: "code1".to_string(),
+                channel_id: 1234,
+                reason: "reason".to_string(),
+                deleted: true,
+            }]
+        );
+    }
+}
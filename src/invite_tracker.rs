@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct InviteUses {
+    pub uses: u64,
+    /// 0 for unlimited.
+    pub max_uses: u64,
+    /// `None` if it never expires.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl InviteUses {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether the invite may have been deleted because it reached `max_uses`.
+    fn may_be_used_up(&self, now: DateTime<Utc>) -> bool {
+        self.max_uses > 0 && self.uses + 1 == self.max_uses && !self.is_expired(now)
+    }
+}
+
+/// Use counts of the invites of each guild, to find which invite a new member used.
+#[derive(Default)]
+pub struct InviteTracker {
+    guilds: HashMap<u64, HashMap<String, InviteUses>>,
+}
+
+impl InviteTracker {
+    pub fn reset(&mut self, guild_id: u64, invites: HashMap<String, InviteUses>) {
+        self.guilds.insert(guild_id, invites);
+    }
+
+    pub fn insert(&mut self, guild_id: u64, code: String, uses: InviteUses) {
+        self.guilds.entry(guild_id).or_default().insert(code, uses);
+    }
+
+    /// Forget the deleted invite, unless it may have been deleted because it reached `max_uses`.
+    /// Expired invites are forgotten, even if they had one use left.
+    pub fn remove(&mut self, guild_id: u64, code: &str, now: DateTime<Utc>) {
+        if let Some(invites) = self.guilds.get_mut(&guild_id) {
+            if invites
+                .get(code)
+                .is_some_and(|uses| !uses.may_be_used_up(now))
+            {
+                invites.remove(code);
+            }
+        }
+    }
+
+    /// Replace the use counts with the current ones, and returns the invites used since the last time.
+    pub fn update(
+        &mut self,
+        guild_id: u64,
+        current: HashMap<String, InviteUses>,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let before = self.guilds.insert(guild_id, current).unwrap_or_default();
+        let current = &self.guilds[&guild_id];
+
+        let mut used: Vec<String> = before
+            .iter()
+            .filter(|(code, before)| current.get(*code).is_some_and(|c| c.uses > before.uses))
+            .map(|(code, _)| code.clone())
+            .collect();
+
+        if used.is_empty() {
+            // Discord deletes the invite when it reaches `max_uses`.
+            used = before
+                .iter()
+                .filter(|(code, before)| !current.contains_key(*code) && before.may_be_used_up(now))
+                .map(|(code, _)| code.clone())
+                .collect();
+        }
+
+        used.sort();
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn uses(uses: u64, max_uses: u64) -> InviteUses {
+        InviteUses {
+            uses,
+            max_uses,
+            expires_at: None,
+        }
+    }
+
+    fn expiring(uses: u64, max_uses: u64, expires_at: i64) -> InviteUses {
+        InviteUses {
+            expires_at: Some(at(expires_at)),
+            ..self::uses(uses, max_uses)
+        }
+    }
+
+    fn invites(list: &[(&str, InviteUses)]) -> HashMap<String, InviteUses> {
+        list.iter()
+            .map(|(code, uses)| (code.to_string(), *uses))
+            .collect()
+    }
+
+    #[test]
+    fn used_by_count() {
+        let mut tracker = InviteTracker::default();
+        tracker.reset(1, invites(&[("a", uses(0, 0)), ("b", uses(2, 5))]));
+
+        assert_eq!(
+            tracker.update(1, invites(&[("a", uses(0, 0)), ("b", uses(3, 5))]), at(0)),
+            vec!["b".to_string()]
+        );
+        assert_eq!(
+            tracker.update(1, invites(&[("a", uses(0, 0)), ("b", uses(3, 5))]), at(0)),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn used_up_and_deleted() {
+        let mut tracker = InviteTracker::default();
+        tracker.reset(1, invites(&[("a", uses(0, 0)), ("b", uses(0, 1))]));
+        tracker.insert(1, "c".to_string(), uses(0, 1));
+
+        // Deletion of the last-use invite is kept until the member is added.
+        tracker.remove(1, "c", at(0));
+        tracker.remove(1, "a", at(0));
+
+        assert_eq!(
+            tracker.update(1, invites(&[("b", uses(0, 1))]), at(0)),
+            vec!["c".to_string()]
+        );
+    }
+
+    #[test]
+    fn expired_unused() {
+        let mut tracker = InviteTracker::default();
+        tracker.reset(
+            1,
+            invites(&[
+                ("a", expiring(0, 1, 100)),
+                ("b", expiring(0, 1, 100)),
+                ("c", expiring(0, 1, 1000)),
+            ]),
+        );
+
+        // "a" expires unused and is deleted, and "b" expires without the deletion known.
+        tracker.remove(1, "a", at(100));
+        tracker.remove(1, "c", at(200));
+
+        assert_eq!(
+            tracker.update(1, invites(&[]), at(200)),
+            vec!["c".to_string()]
+        );
+
+        // Joined with the invite not tracked, such as the vanity URL.
+        tracker.reset(1, invites(&[("d", expiring(0, 1, 100))]));
+        assert_eq!(
+            tracker.update(1, invites(&[]), at(100) + Duration::seconds(1)),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn unknown_guild() {
+        let mut tracker = InviteTracker::default();
+
+        assert_eq!(
+            tracker.update(1, invites(&[("a", uses(1, 0))]), at(0)),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -1,16 +1,48 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc};
 
-use config::load_config;
+use access_list::AccessListFile;
+use api_misskey::Note;
+use approval::{decision_from_reaction, decision_from_reply, Decision};
+use chrono::{DateTime, Utc};
+use cli::{Args, USAGE};
+use config::{load_config, CommandsConfig, Config, InvitePolicy};
+use futures::future::join_all;
+use invite_provider::{invite_url, InviteProvider, IssuedInvite};
+use messages::{reply, Messages};
 use moko256_systemd_stdio_logger as logger;
+use reload::{start_watching_reload, SharedConfig};
 use repo_discord::RepoDiscord;
-use repo_misskey::RepoMisskey;
+use repo_misskey::{MisskeyEvent, RepoMisskey};
+use repo_storage::{InviteOutcome, InviteRecord, PendingRequest, RepoStorage};
+use request_service::{RequestDecision, RequestService};
+use revocation::{find_outstanding_invites_of, revoke_invites, start_watching_eligibility};
+use template::{format_time, render};
 
+mod access_list;
 mod api_misskey;
 mod api_misskey_stream;
+mod approval;
+mod cli;
+mod command;
 mod config;
+mod eligibility;
+mod invite_provider;
+mod invite_tracker;
+mod messages;
+#[cfg(test)]
+mod mock_misskey;
+mod rate_limit;
+mod reload;
 mod repo_discord;
 mod repo_misskey;
+mod repo_storage;
+mod request_service;
+mod revocation;
+mod routing;
 mod simple_retry;
+mod template;
+#[cfg(test)]
+mod test_fixtures;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -20,82 +52,939 @@ async fn main() -> Result<(), Box<dyn Error>> {
     ])
     .unwrap();
 
-    let config = load_config();
-
-    let (repo_discord, discord_task) = RepoDiscord::create_and_start(&config).await;
-
-    let repo_misskey = RepoMisskey::new(&config);
-    let misskey_task = repo_misskey.start_watching_mention(|note| async {
-        let note = note;
-
-        if note.reply_id.is_none() {
-            // The note is not reply.
-
-            if let Some(text) = &note.text {
-                if text.starts_with(&config.misskey_bot_username) {
-                    // The note has text and is mention.
-
-                    let result: Result<(), Box<dyn Error>> = (|| async {
-                        // Send invite url if the user is local user.
-                        match &note.user.host {
-                            None => {
-                                // Generate and send invite url.
-                                let reason = format!(
-                                    "@{}@{} ({})",
-                                    note.user.username, config.misskey_host, note.user.id
-                                );
-                                let url = repo_discord.generate_invite_url(&reason).await?;
-
-                                // Send reply
-                                let msg = format!(
-                                    "@{} {}\n{}",
-                                    note.user.username, config.bot_reply_message_ok_invite, url
-                                );
-                                repo_misskey.post_reply_dm(&note, msg, true).await?;
-
-                                log::info!(
-                                    "Accepted request from: @{} ({}) \"{}\", code: `{}`",
-                                    note.user.username,
-                                    note.user.id,
-                                    text,
-                                    url
-                                );
-                            }
-                            Some(host) => {
-                                // Reject request because the note is from remote.
-                                let msg = format!(
-                                    "@{} {}",
-                                    note.user.username, config.bot_reply_message_err_remote_user
-                                );
-                                repo_misskey.post_reply_dm(&note, msg, false).await?;
-
-                                log::info!(
-                                    "Rejected request from remote user: @{}@{} ({}) \"{}\"",
-                                    note.user.username,
-                                    host,
-                                    note.user.id,
-                                    text
-                                )
-                            }
-                        }
-
-                        Ok(())
-                    })()
-                    .await;
-
-                    if let Err(err) = result {
-                        log::error!(
-                            "Error occured during processing request ({:?}): {}",
-                            note,
-                            err
-                        );
-                    }
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = match load_config(&args.config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}: {}", args.config_path.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    let accounts = config.accounts();
+
+    let access_lists = accounts
+        .iter()
+        .map(|account| {
+            account
+                .access_list
+                .as_ref()
+                .map(|list_config| {
+                    AccessListFile::open(list_config.path.clone())
+                        .map_err(|err| format!("{}: {}", list_config.path, err))
+                })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>();
+    let access_lists = match access_lists {
+        Ok(access_lists) => access_lists,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if args.check_config {
+        println!("{}: OK", args.config_path.display());
+        return Ok(());
+    }
+
+    let repo_storage = Arc::new(RepoStorage::open(&config.storage_path).unwrap());
+    repo_storage
+        .assign_account(&config.misskey_account())
+        .unwrap();
+
+    let repo_misskeys: Vec<RepoMisskey> = accounts.iter().map(RepoMisskey::new).collect();
+
+    let shared_config = Arc::new(SharedConfig::new(config));
+
+    let (repo_discord, discord_task) =
+        RepoDiscord::create_and_start(Arc::clone(&shared_config), Arc::clone(&repo_storage)).await;
+
+    let misskey_tasks = repo_misskeys
+        .iter()
+        .enumerate()
+        .map(|(index, repo_misskey)| {
+            let shared_config = &shared_config;
+            let repo_discord = &repo_discord;
+            let repo_storage = &repo_storage;
+            let access_list = access_lists[index].as_ref();
+
+            repo_misskey.start_watching(repo_storage, move |event| async move {
+                // Each event uses the config at that time, even if it is reloaded during processing.
+                let config = shared_config.account(index);
+
+                on_event(
+                    &config,
+                    repo_discord,
+                    repo_misskey,
+                    repo_storage,
+                    access_list,
+                    event,
+                )
+                .await
+            })
+        });
+    let misskey_task = join_all(misskey_tasks);
+
+    let revocation_task =
+        start_watching_eligibility(&shared_config, &repo_discord, &repo_misskeys, &repo_storage);
+
+    let reload_task = start_watching_reload(&args.config_path, &shared_config, &repo_discord);
+
+    let (_, _, _, _) = tokio::join!(misskey_task, discord_task, revocation_task, reload_task);
+
+    Ok(())
+}
+
+/// Process the event, and returns `false` if the note failed and is left to be retried by the next backfill.
+async fn on_event(
+    config: &Config,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    access_list: Option<&AccessListFile>,
+    event: MisskeyEvent,
+) -> bool {
+    match event {
+        MisskeyEvent::Mention(note) => {
+            let service = RequestService::new(config, invite_provider, repo_storage);
+
+            // Other mentions are ignored before fetching anything.
+            if !service.is_request(&note) || !mark_processed(config, repo_storage, &note.id) {
+                return true;
+            }
+
+            let result = on_mention(
+                config,
+                &service,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                access_list,
+                &note,
+            )
+            .await;
+
+            if let Err(err) = result {
+                log::error!(
+                    "Error occured during processing request ({:?}): {}",
+                    note,
+                    err
+                );
+
+                match unmark_failed_request(config, repo_storage, &note.id) {
+                    Ok(unmarked) => return !unmarked,
+                    Err(err) => log::error!("Failed to unmark the note ({}): {}", note.id, err),
+                }
+            }
+        }
+        MisskeyEvent::Reply(note) => {
+            // Moderator's reply to the notice.
+            let Some(approval) = &config.approval else {
+                return true;
+            };
+            let (Some(notice_note_id), Some(text)) = (&note.reply_id, &note.text) else {
+                return true;
+            };
+            let Some(decision) = decision_from_reply(text) else {
+                return true;
+            };
+            if !approval.moderator_user_ids.contains(&note.user.id) {
+                return true;
+            }
+            if !mark_processed(config, repo_storage, &note.id) {
+                return true;
+            }
+
+            let result = on_moderator_decision(
+                config,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                notice_note_id,
+                decision,
+            )
+            .await;
+
+            if let Err(err) = result {
+                log::error!(
+                    "Error occured during processing decision ({:?}): {}",
+                    note,
+                    err
+                );
+
+                match unmark_failed_decision(config, repo_storage, &note.id, notice_note_id) {
+                    Ok(unmarked) => return !unmarked,
+                    Err(err) => log::error!("Failed to unmark the note ({}): {}", note.id, err),
                 }
             }
         }
+        MisskeyEvent::Reaction {
+            note,
+            user_id,
+            reaction,
+        } => {
+            // Moderator's reaction to the notice.
+            let Some(approval) = &config.approval else {
+                return true;
+            };
+            let Some(decision) = decision_from_reaction(approval, &reaction) else {
+                return true;
+            };
+            if !approval.moderator_user_ids.contains(&user_id) {
+                return true;
+            }
+
+            let result = on_moderator_decision(
+                config,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                &note.id,
+                decision,
+            )
+            .await;
+
+            if let Err(err) = result {
+                log::error!(
+                    "Error occured during processing decision ({:?}, {}): {}",
+                    note,
+                    reaction,
+                    err
+                );
+            }
+        }
+    }
+
+    true
+}
+
+/// Mark the note processed, and returns `false` if it is already processed, such as delivered again.
+fn mark_processed(config: &Config, repo_storage: &RepoStorage, note_id: &str) -> bool {
+    // The same note can mention several bot accounts on the host.
+    match repo_storage.mark_processed(&config.misskey_account(), note_id, Utc::now()) {
+        Ok(true) => true,
+        Ok(false) => {
+            log::info!("Skip the note already processed: {}", note_id);
+            false
+        }
+        Err(err) => {
+            log::error!("Failed to mark the note processed ({}): {}", note_id, err);
+            false
+        }
+    }
+}
+
+/// Clear the mark of the request failed to process, so the redelivery or the backfill retries it,
+/// unless the invite has been issued or the moderators have been asked. Returns whether it is cleared.
+fn unmark_failed_request(
+    config: &Config,
+    repo_storage: &RepoStorage,
+    note_id: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let account = config.misskey_account();
+    let record = repo_storage.find_request_by_note(&account, note_id)?;
+
+    let is_retried = record.is_none_or(|record| record.outcome == InviteOutcome::Failed);
+    if is_retried {
+        repo_storage.unmark_processed(&account, note_id)?;
+    }
+
+    Ok(is_retried)
+}
+
+/// Clear the mark of the moderator's reply failed to process, so it is retried,
+/// unless the request has been decided. Returns whether it is cleared.
+fn unmark_failed_decision(
+    config: &Config,
+    repo_storage: &RepoStorage,
+    note_id: &str,
+    notice_note_id: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let account = config.misskey_account();
+    let Some(pending) = repo_storage.find_pending_by_notice(&account, notice_note_id)? else {
+        return Ok(false);
+    };
+    let record = repo_storage.find_request_by_note(&account, &pending.request_note_id)?;
+
+    let is_undecided = record.is_none_or(|record| {
+        matches!(
+            record.outcome,
+            InviteOutcome::Pending | InviteOutcome::Failed
+        )
     });
+    if is_undecided {
+        repo_storage.unmark_processed(&account, note_id)?;
+    }
+
+    Ok(is_undecided)
+}
+
+async fn on_mention(
+    config: &Config,
+    service: &RequestService<'_>,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    access_list: Option<&AccessListFile>,
+    note: &Note,
+) -> Result<(), Box<dyn Error>> {
+    let requested_at = Utc::now();
+    let text = note.text.as_deref().unwrap_or_default();
+
+    let user = if service.needs_user() {
+        Some(repo_misskey.fetch_user(&note.user.id).await?)
+    } else {
+        None
+    };
+    let lang = user.as_ref().and_then(|user| user.lang.as_deref());
+    let messages = Messages::new(&config.messages, text, lang);
+
+    let access_list = access_list.map(AccessListFile::load);
+    let decision = service
+        .decide(
+            note,
+            user.as_ref(),
+            access_list.as_deref(),
+            repo_misskey.fetch_relation(&note.user.id),
+            requested_at,
+        )
+        .await?;
+    let local_only = note.user.host.is_none();
+
+    let (outcome, message) = match &decision {
+        RequestDecision::Ignore => return Ok(()),
+        RequestDecision::Help(commands) => {
+            let message = messages.get("commands.message_help", &commands.message_help);
+            let msg = reply(&note.user, &config.misskey_host, message);
+            repo_misskey.post_reply_dm(note, msg, local_only).await?;
+
+            return Ok(());
+        }
+        RequestDecision::Status(commands) => {
+            return on_status(
+                config,
+                commands,
+                &messages,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                note,
+            )
+            .await;
+        }
+        RequestDecision::Cancel(commands) => {
+            return on_cancel(
+                config,
+                commands,
+                &messages,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                note,
+            )
+            .await;
+        }
+        RequestDecision::Revoke { target } => {
+            return on_revoke(
+                config,
+                &messages,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                note,
+                target,
+            )
+            .await;
+        }
+        RequestDecision::Reissue { code, issued } => {
+            let mut record = InviteRecord::new(
+                &config.misskey_account(),
+                note,
+                requested_at,
+                InviteOutcome::Reissued,
+            );
+            record.invite_code = Some(code.clone());
+            record.reason = issued.reason.clone();
+            record.policy = issued.policy.clone();
+            record.expires_at = issued.expires_at;
+            repo_storage.record_request(&record)?;
+
+            let msg = invite_reply(
+                config,
+                &messages,
+                note,
+                issued.policy.as_deref(),
+                code,
+                issued.expires_at,
+            );
+            repo_misskey.post_reply_dm(note, msg, local_only).await?;
+
+            log::info!(
+                "Reissued invite to: @{} ({}) \"{}\", code: `{}`",
+                note.user.username,
+                note.user.id,
+                text,
+                invite_url(code)
+            );
+
+            return Ok(());
+        }
+        RequestDecision::AskApproval {
+            approval,
+            policy,
+            notify,
+        } => {
+            if *notify {
+                let notice = render(
+                    &approval.message_notice,
+                    &[
+                        ("username", &note.user.username),
+                        ("user_id", &note.user.id),
+                        ("text", text),
+                    ],
+                );
+                let notice_note_id = repo_misskey
+                    .post_notice(&approval.moderator_user_ids, notice)
+                    .await?;
+
+                repo_storage.add_pending(&PendingRequest {
+                    account: config.misskey_account(),
+                    notice_note_id,
+                    request_note_id: note.id.clone(),
+                    user_id: note.user.id.clone(),
+                    username: note.user.username.clone(),
+                    policy: policy.clone(),
+                    requested_at,
+                })?;
+            }
 
-    let (_, _) = tokio::join!(misskey_task, discord_task);
+            let mut record = InviteRecord::new(
+                &config.misskey_account(),
+                note,
+                requested_at,
+                InviteOutcome::Pending,
+            );
+            record.policy = policy.clone();
+            repo_storage.record_request(&record)?;
+
+            let message = messages.get("approval.message_pending", &approval.message_pending);
+            let msg = reply(&note.user, &config.misskey_host, message);
+            repo_misskey.post_reply_dm(note, msg, local_only).await?;
+
+            log::info!(
+                "Queued request for approval: @{} ({}) \"{}\"",
+                note.user.username,
+                note.user.id,
+                text
+            );
+
+            return Ok(());
+        }
+        RequestDecision::Invite { policy } => {
+            // Generate and send invite url.
+            let invite = issue_invite(
+                config,
+                invite_provider,
+                repo_storage,
+                note,
+                policy.as_deref(),
+                requested_at,
+            )
+            .await?;
+
+            // Send reply
+            let msg = invite_reply(
+                config,
+                &messages,
+                note,
+                policy.as_deref(),
+                &invite.code,
+                invite.expires_at,
+            );
+            repo_misskey.post_reply_dm(note, msg, local_only).await?;
+
+            log::info!(
+                "Accepted request from: @{} ({}) \"{}\", code: `{}`",
+                note.user.username,
+                note.user.id,
+                text,
+                invite.url()
+            );
+
+            return Ok(());
+        }
+        RequestDecision::RejectRemote => (
+            InviteOutcome::RejectedRemoteUser,
+            messages
+                .get(
+                    "bot_reply_message_err_remote_user",
+                    &config.bot_reply_message_err_remote_user,
+                )
+                .to_string(),
+        ),
+        RequestDecision::RejectDeniedUser(list_config) => (
+            InviteOutcome::RejectedDeniedUser,
+            messages
+                .get(
+                    "access_list.message_denied_user",
+                    &list_config.message_denied_user,
+                )
+                .to_string(),
+        ),
+        RequestDecision::RejectDeniedHost(list_config) => (
+            InviteOutcome::RejectedDeniedHost,
+            messages
+                .get(
+                    "access_list.message_denied_host",
+                    &list_config.message_denied_host,
+                )
+                .to_string(),
+        ),
+        RequestDecision::RejectRole(roles, rejection) => (
+            InviteOutcome::RejectedRole,
+            rejection.message(roles, &messages).to_string(),
+        ),
+        RequestDecision::RejectIneligible(eligibility, ineligible) => (
+            InviteOutcome::RejectedIneligible,
+            ineligible.message(eligibility, &messages),
+        ),
+        RequestDecision::RejectRelation(relation_config, _) => (
+            InviteOutcome::RejectedRelation,
+            messages
+                .get("relation.message", &relation_config.message)
+                .to_string(),
+        ),
+        RequestDecision::RejectRateLimited {
+            rate_limit,
+            retry_after,
+        } => (
+            InviteOutcome::RejectedRateLimited,
+            render(
+                messages.get("rate_limit.message", &rate_limit.message),
+                &[("retry_after", &format_time(*retry_after))],
+            ),
+        ),
+    };
+
+    // Reject request, and reply the reason.
+    repo_storage.record_request(&InviteRecord::new(
+        &config.misskey_account(),
+        note,
+        requested_at,
+        outcome,
+    ))?;
+
+    let msg = reply(&note.user, &config.misskey_host, &message);
+    repo_misskey.post_reply_dm(note, msg, local_only).await?;
+
+    log::info!(
+        "Rejected request: @{}@{} ({}) \"{}\", {:?}: {}",
+        note.user.username,
+        note.user.host.as_deref().unwrap_or(&config.misskey_host),
+        note.user.id,
+        text,
+        outcome,
+        message
+    );
+
+    Ok(())
+}
+
+/// Revoke outstanding invites of the user by `@bot revoke <user>` from admins.
+async fn on_revoke(
+    config: &Config,
+    messages: &Messages<'_>,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    note: &Note,
+    target: &str,
+) -> Result<(), Box<dyn Error>> {
+    let Some(revocation) = &config.revocation else {
+        return Ok(());
+    };
+
+    let records = find_outstanding_invites_of(repo_storage, &config.misskey_host, target)?;
+    let count = revoke_invites(invite_provider, repo_storage, &records).await?;
+
+    let message = render(
+        messages.get("revocation.message_revoked", &revocation.message_revoked),
+        &[("count", &count.to_string()), ("target", target)],
+    );
+    let msg = reply(&note.user, &config.misskey_host, &message);
+    repo_misskey
+        .post_reply_dm(note, msg, note.user.host.is_none())
+        .await?;
+
+    log::info!(
+        "Revoked {} invites of {} by admin: @{} ({})",
+        count,
+        target,
+        note.user.username,
+        note.user.id
+    );
+
+    Ok(())
+}
+
+/// Reply whether the user has the pending request or the valid invite.
+async fn on_status(
+    config: &Config,
+    commands: &CommandsConfig,
+    messages: &Messages<'_>,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    note: &Note,
+) -> Result<(), Box<dyn Error>> {
+    let mut message = messages
+        .get(
+            "commands.message_status_none",
+            &commands.message_status_none,
+        )
+        .to_string();
+
+    let account = config.misskey_account();
+    if repo_storage
+        .find_pending_by_user(&account, &note.user.id)?
+        .is_some()
+    {
+        message = messages
+            .get(
+                "commands.message_status_pending",
+                &commands.message_status_pending,
+            )
+            .to_string();
+    } else {
+        for record in
+            repo_storage.find_outstanding_invites_by_user(&account, &note.user.id, Utc::now())?
+        {
+            let Some(code) = &record.invite_code else {
+                continue;
+            };
+            if !invite_provider.is_invite_valid(code).await? {
+                continue;
+            }
+
+            let expires_at = record.expires_at.map(format_time).unwrap_or_default();
+            let policy_message = policy_message(
+                config,
+                record.policy.as_deref(),
+                "message_status_valid",
+                |policy| policy.message_status_valid.as_ref(),
+            );
+            let template = match &policy_message {
+                Some((key, message)) => messages.get(key, message),
+                None => messages.get(
+                    "commands.message_status_valid",
+                    &commands.message_status_valid,
+                ),
+            };
+            message = render(
+                template,
+                &[("url", &invite_url(code)), ("expires_at", &expires_at)],
+            );
+            break;
+        }
+    }
+
+    let msg = reply(&note.user, &config.misskey_host, &message);
+    repo_misskey
+        .post_reply_dm(note, msg, note.user.host.is_none())
+        .await?;
+
+    Ok(())
+}
+
+/// Cancel the pending request and revoke the outstanding invites of the user.
+async fn on_cancel(
+    config: &Config,
+    commands: &CommandsConfig,
+    messages: &Messages<'_>,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    note: &Note,
+) -> Result<(), Box<dyn Error>> {
+    let mut count = 0;
+
+    let account = config.misskey_account();
+    if let Some(pending) = repo_storage.find_pending_by_user(&account, &note.user.id)? {
+        repo_storage.remove_pending(&pending.notice_note_id)?;
+
+        let mut record = InviteRecord::new(
+            &config.misskey_account(),
+            note,
+            pending.requested_at,
+            InviteOutcome::Cancelled,
+        );
+        record.note_id = pending.request_note_id;
+        record.policy = pending.policy;
+        repo_storage.record_request(&record)?;
+
+        count += 1;
+    }
+
+    let records =
+        repo_storage.find_outstanding_invites_by_user(&account, &note.user.id, Utc::now())?;
+    count += revoke_invites(invite_provider, repo_storage, &records).await?;
+
+    let message = if count == 0 {
+        messages
+            .get(
+                "commands.message_nothing_to_cancel",
+                &commands.message_nothing_to_cancel,
+            )
+            .to_string()
+    } else {
+        render(
+            messages.get("commands.message_cancelled", &commands.message_cancelled),
+            &[("count", &count.to_string())],
+        )
+    };
+    let msg = reply(&note.user, &config.misskey_host, &message);
+    repo_misskey
+        .post_reply_dm(note, msg, note.user.host.is_none())
+        .await?;
+
+    log::info!(
+        "Cancelled {} requests and invites of: @{} ({})",
+        count,
+        note.user.username,
+        note.user.id
+    );
+
+    Ok(())
+}
+
+async fn on_moderator_decision(
+    config: &Config,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+    notice_note_id: &str,
+    decision: Decision,
+) -> Result<(), Box<dyn Error>> {
+    let Some(approval) = &config.approval else {
+        return Ok(());
+    };
+    let Some(pending) =
+        repo_storage.find_pending_by_notice(&config.misskey_account(), notice_note_id)?
+    else {
+        // Not a notice, or already decided.
+        return Ok(());
+    };
+
+    // The request stays pending until the outcome is recorded,
+    // so moderators can decide it again if the followings fail.
+    let note = repo_misskey.fetch_note(&pending.request_note_id).await?;
+    let local_only = note.user.host.is_none();
+
+    let lang = if config.messages.is_empty() {
+        None
+    } else {
+        repo_misskey.fetch_user(&note.user.id).await?.lang
+    };
+    let messages = Messages::new(
+        &config.messages,
+        note.text.as_deref().unwrap_or_default(),
+        lang.as_deref(),
+    );
+
+    match decision {
+        Decision::Approve => {
+            // The user may have got another invite approved while this was pending.
+            let service = RequestService::new(config, invite_provider, repo_storage);
+            let retry_after = service.rate_limited_until(&note.user.id, Utc::now())?;
+            if let (Some(rate_limit), Some(retry_after)) = (&config.rate_limit, retry_after) {
+                let mut record = InviteRecord::new(
+                    &config.misskey_account(),
+                    &note,
+                    pending.requested_at,
+                    InviteOutcome::RejectedRateLimited,
+                );
+                record.policy = pending.policy;
+                repo_storage.record_request(&record)?;
+                repo_storage.remove_pending(notice_note_id)?;
+
+                let message = render(
+                    messages.get("rate_limit.message", &rate_limit.message),
+                    &[("retry_after", &format_time(retry_after))],
+                );
+                let msg = reply(&note.user, &config.misskey_host, &message);
+                repo_misskey.post_reply_dm(&note, msg, local_only).await?;
+
+                log::info!(
+                    "Rejected approved request: @{} ({}), rate limited until {}",
+                    note.user.username,
+                    note.user.id,
+                    retry_after
+                );
+                return Ok(());
+            }
+
+            let policy_name = pending.policy.as_deref();
+            let invite = issue_invite(
+                config,
+                invite_provider,
+                repo_storage,
+                &note,
+                policy_name,
+                pending.requested_at,
+            )
+            .await?;
+            repo_storage.remove_pending(notice_note_id)?;
+
+            let msg = invite_reply(
+                config,
+                &messages,
+                &note,
+                policy_name,
+                &invite.code,
+                invite.expires_at,
+            );
+            repo_misskey.post_reply_dm(&note, msg, local_only).await?;
+
+            log::info!(
+                "Approved request from: @{} ({}), code: `{}`",
+                note.user.username,
+                note.user.id,
+                invite.url()
+            );
+        }
+        Decision::Deny => {
+            let mut record = InviteRecord::new(
+                &config.misskey_account(),
+                &note,
+                pending.requested_at,
+                InviteOutcome::Denied,
+            );
+            record.policy = pending.policy;
+            repo_storage.record_request(&record)?;
+            repo_storage.remove_pending(notice_note_id)?;
+
+            let message = messages.get("approval.message_denied", &approval.message_denied);
+            let msg = reply(&note.user, &config.misskey_host, message);
+            repo_misskey.post_reply_dm(&note, msg, local_only).await?;
+
+            log::info!(
+                "Denied request from: @{} ({})",
+                note.user.username,
+                note.user.id
+            );
+        }
+    }
 
     Ok(())
 }
+
+/// Reply with the invite. The url is appended if the message does not contain `{url}`.
+fn invite_reply(
+    config: &Config,
+    messages: &Messages,
+    note: &Note,
+    policy_name: Option<&str>,
+    code: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> String {
+    let url = invite_url(code);
+    let expires_at = expires_at.map(format_time).unwrap_or_default();
+
+    let policy_message = policy_message(config, policy_name, "message_ok_invite", |policy| {
+        policy.message_ok_invite.as_ref()
+    });
+    let message = match &policy_message {
+        Some((key, message)) => messages.get(key, message),
+        None => messages.get(
+            "bot_reply_message_ok_invite",
+            &config.bot_reply_message_ok_invite,
+        ),
+    };
+    let rendered = render(message, &[("url", &url), ("expires_at", &expires_at)]);
+    let mut msg = reply(&note.user, &config.misskey_host, &rendered);
+    if !message.contains("{url}") {
+        msg.push('\n');
+        msg.push_str(&url);
+    }
+
+    msg
+}
+
+/// The message of the named policy, and its translation key `invite_policies.<name>.<field>`.
+fn policy_message<'a>(
+    config: &'a Config,
+    policy_name: Option<&str>,
+    field: &str,
+    message: impl Fn(&'a InvitePolicy) -> Option<&'a String>,
+) -> Option<(String, &'a String)> {
+    let name = policy_name?;
+    let message = message(config.invite_policies.get(name)?)?;
+
+    Some((format!("invite_policies.{}.{}", name, field), message))
+}
+
+/// Generate the invite and record it.
+async fn issue_invite(
+    config: &Config,
+    invite_provider: &dyn InviteProvider,
+    repo_storage: &RepoStorage,
+    note: &Note,
+    policy_name: Option<&str>,
+    requested_at: DateTime<Utc>,
+) -> Result<IssuedInvite, Box<dyn Error>> {
+    let policy = policy_name
+        .and_then(|name| config.invite_policies.get(name))
+        .unwrap_or(&config.invite);
+
+    let reason = format!(
+        "@{}@{} ({})",
+        note.user.username,
+        note.user.host.as_deref().unwrap_or(&config.misskey_host),
+        note.user.id
+    );
+    let invite = match invite_provider
+        .generate_invite(
+            policy.channel_id.unwrap_or(config.discord_channel_invite),
+            policy,
+            &reason,
+        )
+        .await
+    {
+        Ok(invite) => invite,
+        Err(err) => {
+            let mut record = InviteRecord::new(
+                &config.misskey_account(),
+                note,
+                requested_at,
+                InviteOutcome::Failed,
+            );
+            record.reason = Some(reason);
+            record.policy = policy_name.map(str::to_string);
+            repo_storage.record_request(&record)?;
+
+            return Err(err);
+        }
+    };
+    let mut record = InviteRecord::new(
+        &config.misskey_account(),
+        note,
+        requested_at,
+        InviteOutcome::Issued,
+    );
+    record.invite_code = Some(invite.code.clone());
+    record.reason = Some(reason);
+    record.policy = policy_name.map(str::to_string);
+    record.expires_at = invite.expires_at;
+    repo_storage.record_request(&record)?;
+
+    Ok(invite)
+}
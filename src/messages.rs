@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use crate::{api_misskey::User, template::render};
+
+/// Reply messages translated into the language of the requester.
+#[derive(Default)]
+pub struct Messages<'a> {
+    translation: Option<&'a BTreeMap<String, String>>,
+}
+
+impl<'a> Messages<'a> {
+    /// Choose the language by the keyword `lang:<language>` in the mention, or the language of the Misskey account.
+    /// Both `ja-JP` and `ja` match the translation `[messages.ja]`.
+    pub fn new(
+        translations: &'a BTreeMap<String, BTreeMap<String, String>>,
+        text: &str,
+        user_lang: Option<&str>,
+    ) -> Messages<'a> {
+        let keyword = text
+            .split_whitespace()
+            .find_map(|word| word.strip_prefix("lang:"));
+
+        let translation = keyword.into_iter().chain(user_lang).find_map(|lang| {
+            translations.get(lang).or_else(|| {
+                let primary = lang.split(['-', '_']).next()?;
+                translations.get(primary)
+            })
+        });
+
+        Messages { translation }
+    }
+
+    /// The translation of the message, or the message itself.
+    /// Keys are the names in the config, such as `bot_reply_message_ok_invite` or `rate_limit.message`.
+    pub fn get(&self, key: &str, message: &'a str) -> &'a str {
+        self.translation
+            .and_then(|translation| translation.get(key))
+            .map_or(message, String::as_str)
+    }
+}
+
+/// Reply to the user, starting with the mention. `{username}` and `{host}` are replaced in every message.
+pub fn reply(user: &User, local_host: &str, message: &str) -> String {
+    let message = render(
+        message,
+        &[
+            ("username", &user.username),
+            ("host", user.host.as_deref().unwrap_or(local_host)),
+        ],
+    );
+    format!("@{} {}", user.username, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{reply, Messages};
+    use crate::api_misskey::User;
+
+    fn translations() -> BTreeMap<String, BTreeMap<String, String>> {
+        BTreeMap::from([
+            (
+                "ja".to_string(),
+                BTreeMap::from([("message".to_string(), "ja".to_string())]),
+            ),
+            (
+                "de".to_string(),
+                BTreeMap::from([("message".to_string(), "de".to_string())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn choose_language() {
+        let translations = translations();
+
+        let messages = Messages::new(&translations, "@bot invite", None);
+        assert_eq!(messages.get("message", "en"), "en");
+
+        let messages = Messages::new(&translations, "@bot invite", Some("ja-JP"));
+        assert_eq!(messages.get("message", "en"), "ja");
+
+        let messages = Messages::new(&translations, "@bot invite lang:de", Some("ja-JP"));
+        assert_eq!(messages.get("message", "en"), "de");
+
+        let messages = Messages::new(&translations, "@bot invite lang:fr", Some("en-US"));
+        assert_eq!(messages.get("message", "en"), "en");
+        assert_eq!(messages.get("other", "en"), "en");
+    }
+
+    #[test]
+    fn reply_with_user() {
+        let user = User {
+            id: "id".to_string(),
+            username: "user".to_string(),
+            host: None,
+        };
+        assert_eq!(
+            reply(&user, "example.com", "Hi {username}@{host}, {url}"),
+            "@user Hi user@example.com, {url}"
+        );
+    }
+}
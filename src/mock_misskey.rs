@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_tungstenite::{tokio::accept_async, tungstenite::Message};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{sleep, timeout},
+};
+
+/// Username of the bot account served by the mock.
+pub const BOT_USERNAME: &str = "bot";
+
+#[derive(Default)]
+struct State {
+    /// Requests to the API, such as `("notes/create", body)`.
+    requests: Vec<(String, Value)>,
+    /// Responses replacing the default ones, by the endpoint.
+    responses: HashMap<String, Value>,
+    /// Number of the next requests to fail, by the endpoint.
+    failures: HashMap<String, usize>,
+    streams: Vec<StreamConnection>,
+    /// Number of the `main` channel connections, including closed ones.
+    main_connections: usize,
+    created_notes: usize,
+}
+
+struct StreamConnection {
+    sender: mpsc::UnboundedSender<Message>,
+    /// Ids of the connected `main` channels.
+    main_channel_ids: Vec<String>,
+}
+
+impl State {
+    fn respond(&mut self, endpoint: &str, body: Value) -> (u16, Value) {
+        self.requests.push((endpoint.to_string(), body.clone()));
+
+        if let Some(failures @ 1..) = self.failures.get_mut(endpoint) {
+            *failures -= 1;
+            return (
+                500,
+                json!({ "error": { "code": "INTERNAL_ERROR", "message": "Internal error occurred." } }),
+            );
+        }
+        if let Some(response) = self.responses.get(endpoint) {
+            return (200, response.clone());
+        }
+        match endpoint {
+            "notes/create" => {
+                self.created_notes += 1;
+                let created_note = json!({
+                    "id": format!("created{}", self.created_notes),
+                    "text": body["text"],
+                    "user": { "id": BOT_USERNAME, "username": BOT_USERNAME, "host": null },
+                    "replyId": body["replyId"],
+                });
+                (200, json!({ "createdNote": created_note }))
+            }
+            "notes/mentions" => (200, json!([])),
+            "users/show" => (
+                200,
+                user_detailed_json(body["userId"].as_str().unwrap_or_default()),
+            ),
+            _ => (
+                400,
+                json!({ "error": { "code": "NO_SUCH_ENDPOINT", "message": "No such endpoint." } }),
+            ),
+        }
+    }
+}
+
+/// Local stand-in for the Misskey server, serving the API over http and the stream over ws.
+pub struct MockMisskey {
+    /// Such as `http://127.0.0.1:12345`, for `misskey_api_url`.
+    pub base_url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockMisskey {
+    pub async fn start() -> MockMisskey {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let accepting = Arc::clone(&state);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, Arc::clone(&accepting)));
+            }
+        });
+
+        MockMisskey { base_url, state }
+    }
+
+    /// Respond to the endpoint with the body, instead of the default one.
+    pub fn set_response(&self, endpoint: &str, body: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.responses.insert(endpoint.to_string(), body);
+    }
+
+    /// Fail the next `count` requests to the endpoint, which are still recorded.
+    pub fn fail_next(&self, endpoint: &str, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.insert(endpoint.to_string(), count);
+    }
+
+    /// Wait until the bot connects to the `main` channel `count` times in total.
+    pub async fn wait_connected(&self, count: usize) {
+        self.wait_until(|state| state.main_connections >= count)
+            .await;
+    }
+
+    /// Wait until the endpoint is requested `count` times, and returns the bodies.
+    pub async fn wait_requests(&self, endpoint: &str, count: usize) -> Vec<Value> {
+        let requests = |state: &State| -> Vec<Value> {
+            state
+                .requests
+                .iter()
+                .filter(|(requested, _)| requested == endpoint)
+                .map(|(_, body)| body.clone())
+                .collect()
+        };
+
+        self.wait_until(|state| requests(state).len() >= count)
+            .await;
+        requests(&self.state.lock().unwrap())
+    }
+
+    /// Send the `mention` event to the connected `main` channels.
+    pub fn mention(&self, note: Value) {
+        self.send_main("mention", note);
+    }
+
+    fn send_main(&self, kind: &str, body: Value) {
+        let state = self.state.lock().unwrap();
+        for stream in &state.streams {
+            for id in &stream.main_channel_ids {
+                let frame = json!({
+                    "type": "channel",
+                    "body": { "id": id, "type": kind, "body": body },
+                });
+                let _ = stream
+                    .sender
+                    .unbounded_send(Message::Text(frame.to_string()));
+            }
+        }
+    }
+
+    /// Close the stream connections, and the bot will reconnect.
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().streams.clear();
+    }
+
+    async fn wait_until(&self, condition: impl Fn(&State) -> bool) {
+        let waiting = async {
+            while !condition(&self.state.lock().unwrap()) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(10), waiting)
+            .await
+            .expect("timed out waiting for the bot");
+    }
+}
+
+/// Note JSON in the form of the Misskey API.
+pub fn note_json(id: &str, user_id: &str, text: &str) -> Value {
+    json!({
+        "id": id,
+        "text": text,
+        "user": { "id": user_id, "username": user_id, "host": null },
+        "replyId": null,
+    })
+}
+
+/// Local user JSON in the form of `users/show`, old and active enough for any eligibility.
+pub fn user_detailed_json(user_id: &str) -> Value {
+    json!({
+        "id": user_id,
+        "username": user_id,
+        "host": null,
+        "createdAt": "2020-01-01T00:00:00.000Z",
+        "notesCount": 1000,
+        "followersCount": 100,
+        "followingCount": 100,
+        "roles": [],
+    })
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<State>>) {
+    // The stream is requested by GET, and the API by POST.
+    let mut method = [0; 4];
+    let Ok(len) = stream.peek(&mut method).await else {
+        return;
+    };
+
+    if method[..len].starts_with(b"GET") {
+        handle_stream(stream, state).await;
+    } else {
+        handle_api(stream, state).await;
+    }
+}
+
+async fn handle_api(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+
+    let header_end = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let path = head.split_whitespace().nth(1).unwrap_or_default();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().ok())?
+        })
+        .unwrap_or(0);
+
+    while buf.len() < header_end + content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+        }
+    }
+    let body = serde_json::from_slice(&buf[header_end..header_end + content_length])
+        .unwrap_or(Value::Null);
+
+    let endpoint = path.trim_start_matches("/api/");
+    let (status, response) = state.lock().unwrap().respond(endpoint, body);
+
+    let response = response.to_string();
+    let message = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        match status {
+            200 => "OK",
+            400 => "Bad Request",
+            _ => "Internal Server Error",
+        },
+        response.len(),
+        response
+    );
+    let _ = stream.write_all(message.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+async fn handle_stream(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let Ok(ws) = accept_async(stream).await else {
+        return;
+    };
+    let (mut sink, mut receiving) = ws.split();
+
+    let (sender, mut outgoing) = mpsc::unbounded();
+    let index = {
+        let mut state = state.lock().unwrap();
+        state.streams.push(StreamConnection {
+            sender,
+            main_channel_ids: Vec::new(),
+        });
+        state.streams.len() - 1
+    };
+
+    loop {
+        tokio::select! {
+            msg = receiving.next() => {
+                // Pings are answered by the websocket itself.
+                let Some(Ok(msg)) = msg else {
+                    return;
+                };
+                let Message::Text(text) = msg else {
+                    continue;
+                };
+                let Ok(msg) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+
+                if msg["type"] == "connect" && msg["body"]["channel"] == "main" {
+                    let mut state = state.lock().unwrap();
+                    if let (Some(stream), Some(id)) =
+                        (state.streams.get_mut(index), msg["body"]["id"].as_str())
+                    {
+                        stream.main_channel_ids.push(id.to_string());
+                    }
+                    state.main_connections += 1;
+                }
+            },
+            msg = outgoing.next() => {
+                let Some(msg) = msg else {
+                    // Disconnected by the test.
+                    let _ = sink.close().await;
+                    return;
+                };
+                if sink.send(msg).await.is_err() {
+                    return;
+                }
+            },
+        }
+    }
+}
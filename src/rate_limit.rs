@@ -0,0 +1,84 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::RateLimitRule;
+
+/// Returns the time when a new invite can be issued, or `None` if it can be issued now.
+pub fn next_allowed_time(
+    rules: &[RateLimitRule],
+    issued_at: &[DateTime<Utc>],
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let period = Duration::hours(rule.period_hours.get().into());
+            let max_invites = rule.max_invites.get() as usize;
+
+            let mut in_period: Vec<DateTime<Utc>> = issued_at
+                .iter()
+                .copied()
+                .filter(|t| *t > now - period)
+                .collect();
+
+            if in_period.len() < max_invites {
+                return None;
+            }
+
+            // The rule allows again when enough of the oldest invites leave the period.
+            in_period.sort();
+            Some(in_period[in_period.len() - max_invites] + period)
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::next_allowed_time;
+    use crate::{config::RateLimitRule, test_fixtures::hours};
+
+    fn rule(period_hours: u32, max_invites: u32) -> RateLimitRule {
+        RateLimitRule {
+            period_hours: NonZeroU32::new(period_hours).unwrap(),
+            max_invites: NonZeroU32::new(max_invites).unwrap(),
+        }
+    }
+
+    #[test]
+    fn no_rules() {
+        assert_eq!(next_allowed_time(&[], &[hours(0)], hours(0)), None);
+    }
+
+    #[test]
+    fn one_per_day() {
+        let rules = [rule(24, 1)];
+
+        assert_eq!(next_allowed_time(&rules, &[], hours(100)), None);
+        assert_eq!(
+            next_allowed_time(&rules, &[hours(90)], hours(100)),
+            Some(hours(114))
+        );
+        assert_eq!(next_allowed_time(&rules, &[hours(70)], hours(100)), None);
+    }
+
+    #[test]
+    fn many_per_week() {
+        let rules = [rule(24, 2), rule(24 * 7, 3)];
+
+        // Daily rule is satisfied, but weekly one is not.
+        assert_eq!(
+            next_allowed_time(&rules, &[hours(10), hours(20), hours(90)], hours(100)),
+            Some(hours(10 + 24 * 7))
+        );
+        // Both rules are violated.
+        assert_eq!(
+            next_allowed_time(&rules, &[hours(20), hours(95), hours(99)], hours(100)),
+            Some(hours(20 + 24 * 7))
+        );
+        assert_eq!(
+            next_allowed_time(&rules, &[hours(95), hours(99)], hours(100)),
+            Some(hours(95 + 24))
+        );
+    }
+}
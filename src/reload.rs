@@ -0,0 +1,242 @@
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    config::{load_config, Config, ConfigError},
+    repo_discord::RepoDiscord,
+};
+
+/// The config which is replaced on reload. Read the current one on each event.
+pub struct SharedConfig {
+    /// Configs for each Misskey account, see [`Config::accounts`].
+    accounts: RwLock<Vec<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig {
+            accounts: RwLock::new(Self::split(config)),
+        }
+    }
+
+    fn split(config: Config) -> Vec<Arc<Config>> {
+        config.accounts().into_iter().map(Arc::new).collect()
+    }
+
+    /// The top level config.
+    pub fn get(&self) -> Arc<Config> {
+        self.account(0)
+    }
+
+    /// The config for the Misskey account. The number of accounts does not change on reload.
+    pub fn account(&self, index: usize) -> Arc<Config> {
+        Arc::clone(&self.accounts.read().unwrap()[index])
+    }
+
+    /// The config for the account such as `@bot@example.com`, or the top level one if it is not found.
+    pub fn find_account(&self, account: Option<&str>) -> Arc<Config> {
+        let accounts = self.accounts.read().unwrap();
+        let found = accounts
+            .iter()
+            .find(|config| Some(config.misskey_account().as_str()) == account);
+
+        Arc::clone(found.unwrap_or(&accounts[0]))
+    }
+
+    fn replace(&self, config: Config) {
+        *self.accounts.write().unwrap() = Self::split(config);
+    }
+}
+
+/// Keep the fields which are used only at startup, and returns the names of the changed ones.
+fn keep_non_reloadable(current: &Config, new: &mut Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+
+    fn keep<T: PartialEq + Clone>(
+        name: &'static str,
+        current: &T,
+        new: &mut T,
+        changed: &mut Vec<&'static str>,
+    ) {
+        if current != new {
+            *new = current.clone();
+            changed.push(name);
+        }
+    }
+
+    keep(
+        "misskey_host",
+        &current.misskey_host,
+        &mut new.misskey_host,
+        &mut changed,
+    );
+    keep(
+        "misskey_api_url",
+        &current.misskey_api_url,
+        &mut new.misskey_api_url,
+        &mut changed,
+    );
+    // The account is the key of the records in the storage.
+    keep(
+        "misskey_bot_username",
+        &current.misskey_bot_username,
+        &mut new.misskey_bot_username,
+        &mut changed,
+    );
+    keep(
+        "misskey_bot_token",
+        &current.misskey_bot_token,
+        &mut new.misskey_bot_token,
+        &mut changed,
+    );
+    keep(
+        "discord_bot_token",
+        &current.discord_bot_token,
+        &mut new.discord_bot_token,
+        &mut changed,
+    );
+    keep(
+        "storage_path",
+        &current.storage_path,
+        &mut new.storage_path,
+        &mut changed,
+    );
+    // The list file is opened at startup, but the messages can be reloaded.
+    match (&current.access_list, &mut new.access_list) {
+        (Some(current_list), Some(new_list)) => keep(
+            "access_list.path",
+            &current_list.path,
+            &mut new_list.path,
+            &mut changed,
+        ),
+        (current_list, new_list) => keep("access_list", current_list, new_list, &mut changed),
+    }
+
+    // Watching Misskey accounts are started at startup.
+    type Account = (String, Option<String>, String, String, Option<String>);
+    let accounts = |config: &Config| -> Vec<Account> {
+        config
+            .misskey_accounts
+            .iter()
+            .map(|account| {
+                (
+                    account.misskey_host.clone(),
+                    account.misskey_api_url.clone(),
+                    account.misskey_bot_username.clone(),
+                    account.misskey_bot_token.clone(),
+                    account.access_list.as_ref().map(|list| list.path.clone()),
+                )
+            })
+            .collect()
+    };
+    if accounts(current) != accounts(new) {
+        new.misskey_accounts = current.misskey_accounts.clone();
+        changed.push("misskey_accounts");
+    }
+
+    changed
+}
+
+/// Load the config again, and returns the names of the fields which need restart to apply.
+pub fn reload(path: &Path, config: &SharedConfig) -> Result<Vec<&'static str>, ConfigError> {
+    let mut new = load_config(path)?;
+    let changed = keep_non_reloadable(&config.get(), &mut new);
+    config.replace(new);
+
+    Ok(changed)
+}
+
+/// Reload the config on SIGHUP.
+#[cfg(unix)]
+pub async fn start_watching_reload(path: &Path, config: &SharedConfig, repo_discord: &RepoDiscord) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+
+    while hangup.recv().await.is_some() {
+        match reload(path, config) {
+            Ok(changed) => {
+                log::info!("Reloaded config from {}.", path.display());
+
+                for field in changed {
+                    log::warn!("{} is changed, but needs restart to apply.", field);
+                }
+
+                repo_discord
+                    .set_activity(&config.get().discord_activity_watching)
+                    .await;
+            }
+            Err(err) => log::error!(
+                "Failed to reload config, keep using the current one: {}",
+                err
+            ),
+        }
+    }
+}
+
+/// Signals are not supported, so never reload.
+#[cfg(not(unix))]
+pub async fn start_watching_reload(
+    _path: &Path,
+    _config: &SharedConfig,
+    _repo_discord: &RepoDiscord,
+) {
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::keep_non_reloadable;
+    use crate::test_fixtures::full_config;
+
+    #[test]
+    fn keep_startup_fields() {
+        let current = full_config();
+
+        let mut new = full_config();
+        new.bot_reply_message_ok_invite = "changed".to_string();
+        new.discord_activity_watching = "changed".to_string();
+        assert!(keep_non_reloadable(&current, &mut new).is_empty());
+        assert_eq!(new.bot_reply_message_ok_invite, "changed");
+
+        let mut new = full_config();
+        new.discord_bot_token = "changed".to_string();
+        new.access_list = None;
+        assert_eq!(
+            keep_non_reloadable(&current, &mut new),
+            vec!["discord_bot_token", "access_list"]
+        );
+        assert_eq!(new, current);
+
+        let mut new = full_config();
+        let list = new.access_list.as_mut().unwrap();
+        list.path = "changed".to_string();
+        list.message_denied_user = "changed".to_string();
+        assert_eq!(
+            keep_non_reloadable(&current, &mut new),
+            vec!["access_list.path"]
+        );
+        let list = new.access_list.unwrap();
+        assert_eq!(list.path, current.access_list.as_ref().unwrap().path);
+        assert_eq!(list.message_denied_user, "changed");
+
+        let mut new = full_config();
+        new.misskey_accounts[0].misskey_bot_token = "changed".to_string();
+        assert_eq!(
+            keep_non_reloadable(&current, &mut new),
+            vec!["misskey_accounts"]
+        );
+        assert_eq!(new, current);
+
+        let mut new = full_config();
+        new.misskey_bot_username = "@changed".to_string();
+        new.misskey_accounts[0].misskey_bot_username = "@changed".to_string();
+        assert_eq!(
+            keep_non_reloadable(&current, &mut new),
+            vec!["misskey_bot_username", "misskey_accounts"]
+        );
+        assert_eq!(new, current);
+    }
+}
@@ -1,85 +1,467 @@
 use std::{
+    collections::HashMap,
     error::Error,
-    sync::{Arc, OnceLock},
+    sync::{Arc, Mutex},
 };
 
+use chrono::{DateTime, Duration, Utc};
 use serde_json::Number;
 use serenity::{
     async_trait,
-    http::Http,
+    client::bridge::gateway::ShardManager,
+    http::{Http, HttpError, StatusCode},
     json::JsonMap,
-    model::prelude::{Activity, Ready},
-    prelude::{Context, EventHandler},
+    model::{
+        event::{InviteCreateEvent, InviteDeleteEvent},
+        prelude::{Activity, GuildId, Member, Ready},
+        Timestamp,
+    },
+    prelude::{Context, EventHandler, GatewayIntents, Mutex as AsyncMutex},
     Client,
 };
 use tokio::task::JoinHandle;
 
-use crate::config::Config;
-
-static INVITE_URL_PARAM: OnceLock<JsonMap> = OnceLock::new();
+use crate::{
+    config::{Config, InvitePolicy, InviteTarget},
+    invite_provider::{InviteProvider, IssuedInvite},
+    invite_tracker::{InviteTracker, InviteUses},
+    reload::SharedConfig,
+    repo_storage::{InviteRecord, MemberLink, RepoStorage},
+    template::render,
+};
 
 pub struct RepoDiscord {
     http: Arc<Http>,
-    ch_invite: u64,
+    shard_manager: Arc<AsyncMutex<ShardManager>>,
 }
 
 impl RepoDiscord {
-    pub async fn create_and_start(config: &Config) -> (RepoDiscord, JoinHandle<()>) {
-        let watching = config.discord_activity_watching.to_string();
+    pub async fn create_and_start(
+        config: Arc<SharedConfig>,
+        storage: Arc<RepoStorage>,
+    ) -> (RepoDiscord, JoinHandle<()>) {
+        let token = config.get().discord_bot_token.clone();
+
+        // GUILD_MEMBERS is privileged, and must be enabled in the developer portal.
+        let intents = GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS;
 
-        let mut client = Client::builder(&config.discord_bot_token, Default::default())
-            .event_handler(Handler { watching })
+        let mut client = Client::builder(&token, intents)
+            .event_handler(Handler {
+                config,
+                storage,
+                invites: Mutex::new(InviteTracker::default()),
+            })
             .await
             .unwrap();
 
         let http = Arc::clone(&client.cache_and_http.http);
+        let shard_manager = Arc::clone(&client.shard_manager);
 
         let handle = tokio::spawn(async move {
             client.start().await.unwrap();
         });
 
-        let ch_invite = config.discord_channel_invite;
+        (
+            RepoDiscord {
+                http,
+                shard_manager,
+            },
+            handle,
+        )
+    }
+
+    /// Update the activity of the connected shards.
+    pub async fn set_activity(&self, watching: &str) {
+        let shard_manager = self.shard_manager.lock().await;
 
-        (RepoDiscord { http, ch_invite }, handle)
+        for runner in shard_manager.runners.lock().await.values() {
+            runner
+                .runner_tx
+                .set_activity(Some(Activity::watching(watching)));
+        }
     }
+}
 
-    pub async fn generate_invite_url(&self, reason: &str) -> Result<String, Box<dyn Error>> {
-        let param = INVITE_URL_PARAM.get_or_init(move || {
-            let mut map = JsonMap::with_capacity(3);
+#[async_trait(?Send)]
+impl InviteProvider for RepoDiscord {
+    async fn generate_invite(
+        &self,
+        channel_id: u64,
+        policy: &InvitePolicy,
+        reason: &str,
+    ) -> Result<IssuedInvite, Box<dyn Error>> {
+        let param = invite_params(policy);
 
-            map.insert(
-                "max_age".to_string(),
-                serde_json::Value::Number(Number::from(3600)), // 1 hour
-            );
+        let invite = self
+            .http
+            .create_invite(channel_id, &param, Some(reason))
+            .await?;
+
+        Ok(IssuedInvite {
+            expires_at: expires_at(invite.created_at, invite.max_age),
+            code: invite.code,
+        })
+    }
+
+    /// Discord deletes the invite when it expires or reaches `max_uses`,
+    /// so the single-use invite is unused if it exists.
+    async fn is_invite_valid(&self, code: &str) -> Result<bool, Box<dyn Error>> {
+        match self.http.get_invite(code, false, false, None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    async fn delete_invite(&self, code: &str) -> Result<bool, Box<dyn Error>> {
+        match self.http.delete_invite(code).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+}
+
+/// Invites with max_age 0 never expire.
+fn expires_at(created_at: Timestamp, max_age: u64) -> Option<DateTime<Utc>> {
+    if max_age > 0 {
+        DateTime::from_timestamp(created_at.unix_timestamp() + max_age as i64, 0)
+    } else {
+        None
+    }
+}
+
+fn is_not_found(err: &serenity::Error) -> bool {
+    matches!(
+        err,
+        serenity::Error::Http(err) if matches!(
+            err.as_ref(),
+            HttpError::UnsuccessfulRequest(res) if res.status_code == StatusCode::NOT_FOUND
+        )
+    )
+}
+
+fn invite_params(policy: &InvitePolicy) -> JsonMap {
+    let mut map = JsonMap::with_capacity(6);
+
+    map.insert(
+        "max_age".to_string(),
+        serde_json::Value::Number(Number::from(policy.max_age)),
+    );
 
+    map.insert(
+        "max_uses".to_string(),
+        serde_json::Value::Number(Number::from(policy.max_uses)),
+    );
+
+    map.insert(
+        "temporary".to_string(),
+        serde_json::Value::Bool(policy.temporary),
+    );
+
+    map.insert("unique".to_string(), serde_json::Value::Bool(policy.unique));
+
+    match policy.target {
+        Some(InviteTarget::Stream { user_id }) => {
             map.insert(
-                "max_uses".to_string(),
+                "target_type".to_string(),
                 serde_json::Value::Number(Number::from(1)),
             );
+            map.insert(
+                "target_user_id".to_string(),
+                serde_json::Value::Number(Number::from(user_id)),
+            );
+        }
+        Some(InviteTarget::EmbeddedApplication { application_id }) => {
+            map.insert(
+                "target_type".to_string(),
+                serde_json::Value::Number(Number::from(2)),
+            );
+            map.insert(
+                "target_application_id".to_string(),
+                serde_json::Value::Number(Number::from(application_id)),
+            );
+        }
+        None => {}
+    }
 
-            map.insert("unique".to_string(), serde_json::Value::Bool(true));
+    map
+}
 
-            map
-        });
+struct Handler {
+    config: Arc<SharedConfig>,
+    storage: Arc<RepoStorage>,
+    invites: Mutex<InviteTracker>,
+}
 
-        let invite = self
-            .http
-            .create_invite(self.ch_invite, param, Some(reason))
-            .await?;
+impl Handler {
+    async fn fetch_invites(
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> serenity::Result<HashMap<String, InviteUses>> {
+        let invites = ctx.http.get_guild_invites(guild_id.0).await?;
+
+        Ok(invites
+            .into_iter()
+            .map(|invite| {
+                let uses = InviteUses {
+                    uses: invite.uses,
+                    max_uses: invite.max_uses,
+                    expires_at: expires_at(invite.created_at, invite.max_age),
+                };
+                (invite.code, uses)
+            })
+            .collect())
+    }
+
+    /// Link the new member to the Misskey user who requested the invite.
+    fn link_member(
+        &self,
+        member: &Member,
+        code: &str,
+    ) -> Result<Option<InviteRecord>, Box<dyn Error>> {
+        let Some(request) = self.storage.find_request_by_code(code)? else {
+            // The invite was not issued by the bot.
+            return Ok(None);
+        };
+
+        self.storage.link_member(&MemberLink {
+            discord_user_id: member.user.id.0,
+            guild_id: member.guild_id.0,
+            user_id: request.user_id.clone(),
+            invite_code: code.to_string(),
+            joined_at: Utc::now(),
+        })?;
+
+        log::info!(
+            "Member joined: {} ({}) is @{} ({}), code: `{}`",
+            member.user.tag(),
+            member.user.id,
+            request.username,
+            request.user_id,
+            code
+        );
+
+        Ok(Some(request))
+    }
+
+    /// Give the role and the nickname to the member who joined with the invite issued by the bot.
+    async fn set_up_member(
+        ctx: &Context,
+        config: &Config,
+        member: &Member,
+        request: &InviteRecord,
+    ) -> serenity::Result<()> {
+        let reason = format!("Joined with the invite for @{}", request.username);
+        let (role_id, nickname) = member_settings(config, request);
 
-        Ok(format!("https://discord.gg/{}", invite.code))
+        if let Some(role_id) = role_id {
+            ctx.http
+                .add_member_role(member.guild_id.0, member.user.id.0, role_id, Some(&reason))
+                .await?;
+        }
+
+        if let Some(nickname) = nickname {
+            let mut map = JsonMap::with_capacity(1);
+            map.insert("nick".to_string(), serde_json::Value::String(nickname));
+
+            ctx.http
+                .edit_member(member.guild_id.0, member.user.id.0, &map, Some(&reason))
+                .await?;
+        }
+
+        Ok(())
     }
 }
 
-struct Handler {
-    watching: String,
+/// The role and the nickname for the member who joined with the invite for the request.
+fn member_settings(config: &Config, request: &InviteRecord) -> (Option<u64>, Option<String>) {
+    let policy = request
+        .policy
+        .as_deref()
+        .and_then(|name| config.invite_policies.get(name))
+        .unwrap_or(&config.invite);
+    let member_config = config.discord_member.as_ref();
+
+    // The role of `[discord_member]` is of the guild of `discord_channel_invite`.
+    let role_id = policy.role_id.or_else(|| {
+        policy
+            .channel_id
+            .is_none()
+            .then(|| member_config?.role_id)
+            .flatten()
+    });
+
+    let nickname = policy
+        .nickname
+        .as_ref()
+        .or_else(|| member_config?.nickname.as_ref())
+        .map(|nickname| {
+            let host = request
+                .host
+                .as_deref()
+                .or(request.account_host())
+                .unwrap_or(&config.misskey_host);
+            let nickname = render(nickname, &[("username", &request.username), ("host", host)]);
+
+            // Discord limits nicknames to 32 characters.
+            nickname.chars().take(32).collect()
+        });
+
+    (role_id, nickname)
 }
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, ctx: Context, _data_about_bot: Ready) {
-        ctx.set_activity(Activity::watching(self.watching.to_string()))
-            .await;
-        log::info!("Connected to Discord stream.")
+    async fn ready(&self, ctx: Context, data_about_bot: Ready) {
+        let watching = self.config.get().discord_activity_watching.clone();
+        ctx.set_activity(Activity::watching(watching)).await;
+        log::info!("Connected to Discord stream.");
+
+        for guild in data_about_bot.guilds {
+            match Self::fetch_invites(&ctx, guild.id).await {
+                Ok(invites) => self.invites.lock().unwrap().reset(guild.id.0, invites),
+                Err(err) => log::warn!("Failed to fetch invites of {}: {}", guild.id, err),
+            }
+        }
+    }
+
+    async fn invite_create(&self, _ctx: Context, data: InviteCreateEvent) {
+        if let Some(guild_id) = data.guild_id {
+            let uses = InviteUses {
+                uses: 0,
+                max_uses: data.max_uses,
+                // The event is received when the invite is created.
+                expires_at: (data.max_age > 0)
+                    .then(|| Utc::now() + Duration::seconds(data.max_age as i64)),
+            };
+            self.invites
+                .lock()
+                .unwrap()
+                .insert(guild_id.0, data.code, uses);
+        }
+    }
+
+    async fn invite_delete(&self, _ctx: Context, data: InviteDeleteEvent) {
+        if let Some(guild_id) = data.guild_id {
+            self.invites
+                .lock()
+                .unwrap()
+                .remove(guild_id.0, &data.code, Utc::now());
+        }
+    }
+
+    async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
+        let invites = match Self::fetch_invites(&ctx, new_member.guild_id).await {
+            Ok(invites) => invites,
+            Err(err) => {
+                log::warn!(
+                    "Failed to fetch invites of {}: {}",
+                    new_member.guild_id,
+                    err
+                );
+                return;
+            }
+        };
+
+        let used = self
+            .invites
+            .lock()
+            .unwrap()
+            .update(new_member.guild_id.0, invites, Utc::now());
+
+        match used.as_slice() {
+            [code] => {
+                let request = match self.link_member(&new_member, code) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        log::error!(
+                            "Error occured during linking member ({}): {}",
+                            new_member.user.id,
+                            err
+                        );
+                        return;
+                    }
+                };
+
+                if let Some(request) = request {
+                    let config = self.config.find_account(request.account.as_deref());
+                    let result = Self::set_up_member(&ctx, &config, &new_member, &request).await;
+
+                    if let Err(err) = result {
+                        log::error!(
+                            "Error occured during setting up member ({}): {}",
+                            new_member.user.id,
+                            err
+                        );
+                    }
+                }
+            }
+            [] => log::info!("Member joined: {}, invite unknown", new_member.user.id),
+            codes => log::warn!(
+                "Member joined: {}, invite ambiguous: {:?}",
+                new_member.user.id,
+                codes
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::member_settings;
+    use crate::{
+        api_misskey::{Note, User},
+        repo_storage::{InviteOutcome, InviteRecord},
+        test_fixtures::full_config,
+    };
+
+    fn request(policy: Option<&str>, host: Option<&str>) -> InviteRecord {
+        let note = Note {
+            id: "note".to_string(),
+            text: None,
+            user: User {
+                id: "user".to_string(),
+                username: "user".to_string(),
+                host: host.map(str::to_string),
+            },
+            reply_id: None,
+        };
+        let mut record = InviteRecord::new(
+            "@test@example.com",
+            &note,
+            Utc::now(),
+            InviteOutcome::Issued,
+        );
+        record.policy = policy.map(str::to_string);
+        record
+    }
+
+    #[test]
+    fn member_settings_by_policy() {
+        let config = full_config();
+
+        assert_eq!(
+            member_settings(&config, &request(None, None)),
+            (Some(5678), Some("@user@example.com".to_string()))
+        );
+        assert_eq!(
+            member_settings(&config, &request(Some("gaming"), Some("game.example.com"))),
+            (Some(3456), Some("user@game.example.com".to_string()))
+        );
+
+        // The channel of the policy may be in another guild.
+        let mut config = config;
+        config.invite_policies.get_mut("gaming").unwrap().role_id = None;
+        assert_eq!(
+            member_settings(&config, &request(Some("gaming"), None)).0,
+            None
+        );
+        assert_eq!(
+            member_settings(&config, &request(Some("stream"), None)).0,
+            Some(5678)
+        );
     }
 }
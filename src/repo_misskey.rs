@@ -1,34 +1,54 @@
-use std::error::Error;
+use std::{cell::Cell, cmp::Ordering, error::Error};
 
 use chrono::Duration;
 use futures::Future;
 
 use crate::{
-    api_misskey::{MisskeyApi, Note, NotesCreateParams},
+    api_misskey::{
+        MisskeyApi, Note, NotesCreateParams, NotesMentionsParams, NotesShowParams, Notification,
+        UserDetailed, UserRelation, UsersRelationParams, UsersShowParams,
+    },
     api_misskey_stream::{MisskeyApiStream, StreamingBodyMain},
     config::Config,
+    repo_storage::{MentionCursor, RepoStorage},
     simple_retry::simple_retry_loop_by_time,
 };
 
+pub enum MisskeyEvent {
+    Mention(Note),
+    /// Reply to the bot's note.
+    Reply(Note),
+    /// Reaction to the bot's note.
+    Reaction {
+        note: Note,
+        user_id: String,
+        reaction: String,
+    },
+}
+
+/// Number of the notes fetched at once in the backfill, which is the maximum of Misskey.
+const BACKFILL_PAGE_LIMIT: u32 = 100;
+
 pub struct RepoMisskey {
     client: MisskeyApi,
     client_stream: MisskeyApiStream,
+    /// See `Config::misskey_account`.
+    account: String,
 }
 
 impl RepoMisskey {
     pub fn new(config: &Config) -> RepoMisskey {
-        let client = MisskeyApi::new(
-            config.misskey_host.clone(),
-            config.misskey_bot_token.clone(),
-        );
-        let client_stream = MisskeyApiStream::new(
-            config.misskey_host.to_string(),
-            config.misskey_bot_token.to_string(),
-        );
+        let base_url = config.misskey_base_url();
+        let stream_base_url = stream_base_url(&base_url);
+
+        let client = MisskeyApi::new(base_url, config.misskey_bot_token.clone());
+        let client_stream =
+            MisskeyApiStream::new(stream_base_url, config.misskey_bot_token.to_string());
 
         RepoMisskey {
             client,
             client_stream,
+            account: config.misskey_account(),
         }
     }
 
@@ -51,20 +71,176 @@ impl RepoMisskey {
         Ok(())
     }
 
-    pub async fn start_watching_mention<F>(&self, on_mention: impl Fn(Note) -> F)
+    /// Post the note which only the users can see, and returns its id.
+    pub async fn post_notice(
+        &self,
+        visible_user_ids: &[String],
+        message: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let response = self
+            .client
+            .notes_create(NotesCreateParams {
+                visibility: "specified",
+                visible_user_ids: visible_user_ids.iter().map(String::as_str).collect(),
+                text: Some(&message),
+                local_only: true,
+                reply_id: None,
+            })
+            .await?;
+
+        Ok(response.created_note.id)
+    }
+
+    pub async fn fetch_note(&self, note_id: &str) -> Result<Note, Box<dyn Error>> {
+        self.client.notes_show(NotesShowParams { note_id }).await
+    }
+
+    pub async fn fetch_user(&self, user_id: &str) -> Result<UserDetailed, Box<dyn Error>> {
+        self.client.users_show(UsersShowParams { user_id }).await
+    }
+
+    pub async fn fetch_relation(&self, user_id: &str) -> Result<UserRelation, Box<dyn Error>> {
+        self.client
+            .users_relation(UsersRelationParams { user_id })
+            .await
+    }
+
+    /// Mentions newer than the note, or all the recent ones without it, from the oldest.
+    async fn fetch_mentions_since(
+        &self,
+        since_id: Option<&str>,
+    ) -> Result<Vec<Note>, Box<dyn Error>> {
+        let notes = self
+            .client
+            .notes_mentions(NotesMentionsParams {
+                limit: BACKFILL_PAGE_LIMIT,
+                since_id,
+            })
+            .await?;
+
+        Ok(newer_notes(notes, since_id))
+    }
+
+    /// Process the mentions missed while disconnected or stopped,
+    /// or start the cursor at the newest mention on the first watching.
+    /// Returns `false` if a mention failed, and the cursor is kept before it for the next backfill.
+    async fn backfill_mentions<F>(
+        &self,
+        repo_storage: &RepoStorage,
+        on_event: &impl Fn(MisskeyEvent) -> F,
+    ) -> Result<bool, Box<dyn Error>>
     where
-        F: Future<Output = ()>,
+        F: Future<Output = bool>,
+    {
+        let Some(cursor) = repo_storage.find_mention_cursor(&self.account)? else {
+            // The first watching does not process the old mentions.
+            let newest = self
+                .client
+                .notes_mentions(NotesMentionsParams {
+                    limit: 1,
+                    since_id: None,
+                })
+                .await?;
+            let cursor = MentionCursor {
+                last_note_id: newest.first().map(|note| note.id.clone()),
+            };
+            repo_storage.save_mention_cursor(&self.account, &cursor)?;
+            return Ok(true);
+        };
+
+        let mut since_id = cursor.last_note_id;
+        let mut cursor_held = false;
+        loop {
+            let notes = self.fetch_mentions_since(since_id.as_deref()).await?;
+            let Some(last) = notes.last() else {
+                return Ok(!cursor_held);
+            };
+            since_id = Some(last.id.clone());
+            log::info!("Backfill {} missed mentions.", notes.len());
+
+            for note in notes {
+                let note_id = note.id.clone();
+
+                // Replies to the bot's notes are also delivered as mentions.
+                let event = if note.reply_id.is_some() {
+                    MisskeyEvent::Reply(note)
+                } else {
+                    MisskeyEvent::Mention(note)
+                };
+                // The later ones are processed, but the cursor stays before the failed one.
+                cursor_held |= !on_event(event).await;
+                if !cursor_held {
+                    advance_cursor(repo_storage, &self.account, &note_id)?;
+                }
+            }
+        }
+    }
+
+    /// Watch the events, and process the mentions missed while disconnected or stopped after connection.
+    /// `on_event` returns `false` if the note failed, and the cursor is not advanced past it.
+    pub async fn start_watching<F>(
+        &self,
+        repo_storage: &RepoStorage,
+        on_event: impl Fn(MisskeyEvent) -> F,
+    ) where
+        F: Future<Output = bool>,
     {
         simple_retry_loop_by_time(Duration::minutes(1), Duration::minutes(30), || async {
+            // Held until the backfill of this connection finishes, and after a failed note,
+            // so the stream does not skip the cursor over the mentions not processed yet.
+            let cursor_held = Cell::new(true);
+
             // Start Streaming API connection.
             let result = self
                 .client_stream
                 .start_main(
-                    || async { log::info!("Connected to Misskey stream.") },
+                    || async {
+                        log::info!("Connected to Misskey stream.");
+
+                        // The notes delivered by both the stream and the backfill are skipped by the handler.
+                        match self.backfill_mentions(repo_storage, &on_event).await {
+                            Ok(true) => cursor_held.set(false),
+                            Ok(false) => log::warn!(
+                                "Failed to process a missed mention, retry after reconnection."
+                            ),
+                            Err(err) => log::warn!("Failed to backfill mentions: {}", err),
+                        }
+                    },
                     |msg| async {
-                        let StreamingBodyMain::Mention(note) = msg;
+                        let event = match msg {
+                            StreamingBodyMain::Mention(note) => MisskeyEvent::Mention(note),
+                            StreamingBodyMain::Reply(note) => MisskeyEvent::Reply(note),
+                            StreamingBodyMain::Notification(Notification {
+                                kind,
+                                user_id: Some(user_id),
+                                note: Some(note),
+                                reaction: Some(reaction),
+                                ..
+                            }) if kind == "reaction" => MisskeyEvent::Reaction {
+                                note,
+                                user_id,
+                                reaction,
+                            },
+                            StreamingBodyMain::Notification(_) => return,
+                        };
+
+                        let note_id = match &event {
+                            MisskeyEvent::Mention(note) | MisskeyEvent::Reply(note) => {
+                                Some(note.id.clone())
+                            }
+                            MisskeyEvent::Reaction { .. } => None,
+                        };
 
-                        on_mention(note).await;
+                        if !on_event(event).await {
+                            cursor_held.set(true);
+                        }
+
+                        if let Some(note_id) = note_id.filter(|_| !cursor_held.get()) {
+                            if let Err(err) = advance_cursor(repo_storage, &self.account, &note_id)
+                            {
+                                log::warn!("Failed to save the mention cursor: {}", err);
+                            }
+                        }
                     },
                 )
                 .await;
@@ -78,3 +254,409 @@ impl RepoMisskey {
         .await;
     }
 }
+
+/// Base url of the stream for the API, `ws` for `http` and `wss` for `https` in any case.
+fn stream_base_url(base_url: &str) -> String {
+    let mut url = reqwest::Url::parse(base_url).expect("misskey_api_url is validated");
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme)
+        .expect("http and https can be replaced with ws and wss");
+
+    url.as_str().trim_end_matches('/').to_string()
+}
+
+/// Compare the note ids, which are sortable by the time in the same length.
+fn compare_ids(a: &str, b: &str) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Move the cursor of the account to the note, unless it is older than the current one.
+fn advance_cursor(
+    repo_storage: &RepoStorage,
+    account: &str,
+    note_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let cursor = repo_storage
+        .find_mention_cursor(account)?
+        .unwrap_or_default();
+    let is_newer = cursor
+        .last_note_id
+        .as_deref()
+        .is_none_or(|last| compare_ids(note_id, last) == Ordering::Greater);
+
+    if is_newer {
+        let cursor = MentionCursor {
+            last_note_id: Some(note_id.to_string()),
+        };
+        repo_storage.save_mention_cursor(account, &cursor)?;
+    }
+
+    Ok(())
+}
+
+/// Notes newer than the id if any, from the oldest, regardless of the order in the response.
+fn newer_notes(mut notes: Vec<Note>, since_id: Option<&str>) -> Vec<Note> {
+    if let Some(since_id) = since_id {
+        notes.retain(|note| compare_ids(&note.id, since_id) == Ordering::Greater);
+    }
+    notes.sort_by(|a, b| compare_ids(&a.id, &b.id));
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{advance_cursor, newer_notes, stream_base_url, RepoMisskey};
+    use crate::{
+        api_misskey::{Note, User},
+        config::Config,
+        invite_provider::{FakeInvite, FakeInviteProvider},
+        mock_misskey::{note_json, MockMisskey, BOT_USERNAME},
+        on_event,
+        repo_storage::{InviteOutcome, MentionCursor, RepoStorage},
+        test_fixtures::full_config,
+    };
+
+    fn note(id: &str) -> Note {
+        Note {
+            id: id.to_string(),
+            text: None,
+            user: User {
+                id: "user".to_string(),
+                username: "user".to_string(),
+                host: None,
+            },
+            reply_id: None,
+        }
+    }
+
+    fn ids(notes: &[Note]) -> Vec<&str> {
+        notes.iter().map(|note| note.id.as_str()).collect()
+    }
+
+    #[test]
+    fn newer() {
+        let notes = vec![note("9k00000003"), note("9k00000001"), note("9k00000002")];
+        assert_eq!(
+            ids(&newer_notes(notes, Some("9k00000001"))),
+            ["9k00000002", "9k00000003"]
+        );
+        assert!(newer_notes(vec![note("9k00000001")], Some("9k00000001")).is_empty());
+        assert_eq!(
+            ids(&newer_notes(
+                vec![note("9k00000002"), note("9k00000001")],
+                None
+            )),
+            ["9k00000001", "9k00000002"]
+        );
+    }
+
+    #[test]
+    fn stream_url() {
+        assert_eq!(stream_base_url("https://example.com"), "wss://example.com");
+        assert_eq!(
+            stream_base_url("HTTP://localhost:3000"),
+            "ws://localhost:3000"
+        );
+        assert_eq!(
+            stream_base_url("http://example.com/misskey"),
+            "ws://example.com/misskey"
+        );
+    }
+
+    #[test]
+    fn cursor() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let last_note_id = || {
+            let cursor = storage.find_mention_cursor("@bot@a").unwrap().unwrap();
+            cursor.last_note_id
+        };
+
+        advance_cursor(&storage, "@bot@a", "9k00000002").unwrap();
+        advance_cursor(&storage, "@bot@a", "9k00000001").unwrap();
+        assert_eq!(last_note_id().as_deref(), Some("9k00000002"));
+        advance_cursor(&storage, "@bot@a", "9k00000003").unwrap();
+        assert_eq!(last_note_id().as_deref(), Some("9k00000003"));
+    }
+
+    fn config(server: &MockMisskey) -> Config {
+        let mut config = full_config();
+        config.misskey_api_url = Some(server.base_url.clone());
+        config.misskey_bot_username = format!("@{}", BOT_USERNAME);
+        // Checks needing the other endpoints or moderators are covered by their own tests.
+        config.roles = None;
+        config.relation = None;
+        config.approval = None;
+        config
+    }
+
+    /// Watch with the handler of the bot, until the test ends.
+    async fn watch<T>(
+        server: &MockMisskey,
+        repo_storage: &RepoStorage,
+        invite_provider: &FakeInviteProvider,
+        test: impl std::future::Future<Output = T>,
+    ) -> T {
+        let config = config(server);
+        let repo_misskey = RepoMisskey::new(&config);
+        let (config, repo_misskey) = (&config, &repo_misskey);
+
+        let watching = repo_misskey.start_watching(repo_storage, move |event| async move {
+            on_event(
+                config,
+                invite_provider,
+                repo_misskey,
+                repo_storage,
+                None,
+                event,
+            )
+            .await
+        });
+
+        tokio::select! {
+            _ = watching => unreachable!(),
+            result = test => result,
+        }
+    }
+
+    fn invite_reply_body(note_id: &str, code: &str) -> Value {
+        json!({
+            "i": "misskey-token",
+            "visibility": "specified",
+            "visibleUserIds": ["user"],
+            "text": format!("@user bot_reply_message_ok_invite\nhttps://discord.gg/{}", code),
+            "localOnly": true,
+            "replyId": note_id,
+        })
+    }
+
+    fn invites(codes: &[&str]) -> Vec<FakeInvite> {
+        codes
+            .iter()
+            .map(|code| FakeInvite {
+                code: code.to_string(),
+                channel_id: 1234,
+                reason: "@user@example.com (user)".to_string(),
+                deleted: false,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reply_to_mention() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+
+        let (users, replies) = watch(&server, &storage, &provider, async {
+            server.wait_connected(1).await;
+            server.mention(note_json("9k00000001", "user", "@bot invite"));
+            let replies = server.wait_requests("notes/create", 1).await;
+            (server.wait_requests("users/show", 1).await, replies)
+        })
+        .await;
+
+        assert_eq!(users, [json!({ "i": "misskey-token", "userId": "user" })]);
+        assert_eq!(replies, [invite_reply_body("9k00000001", "code1")]);
+        assert_eq!(provider.invites(), invites(&["code1"]));
+
+        let account = config(&server).misskey_account();
+        let record = storage.find_request_by_note(&account, "9k00000001");
+        assert_eq!(record.unwrap().unwrap().outcome, InviteOutcome::Issued);
+    }
+
+    #[tokio::test]
+    async fn backfill_after_reconnect() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+        let mention = |id| note_json(id, "user", "@bot invite");
+        server.set_response("notes/mentions", json!([mention("9k00000001")]));
+
+        let (mentions, replies) = watch(&server, &storage, &provider, async {
+            server.wait_connected(1).await;
+            server.wait_requests("notes/mentions", 1).await;
+
+            // Mentioned while disconnected.
+            server.set_response(
+                "notes/mentions",
+                json!([mention("9k00000002"), mention("9k00000001")]),
+            );
+            server.disconnect();
+            server.wait_connected(2).await;
+
+            let mentions = server.wait_requests("notes/mentions", 3).await;
+            (mentions, server.wait_requests("notes/create", 1).await)
+        })
+        .await;
+
+        assert_eq!(
+            mentions,
+            [
+                json!({ "i": "misskey-token", "limit": 1, "sinceId": null }),
+                json!({ "i": "misskey-token", "limit": 100, "sinceId": "9k00000001" }),
+                json!({ "i": "misskey-token", "limit": 100, "sinceId": "9k00000002" }),
+            ]
+        );
+        assert_eq!(replies, [invite_reply_body("9k00000002", "code1")]);
+    }
+
+    #[tokio::test]
+    async fn backfill_without_mentions_before() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+
+        let (mentions, replies) = watch(&server, &storage, &provider, async {
+            server.wait_connected(1).await;
+            server.wait_requests("notes/mentions", 1).await;
+
+            // The first mention, while disconnected.
+            server.set_response(
+                "notes/mentions",
+                json!([note_json("9k00000001", "user", "@bot invite")]),
+            );
+            server.disconnect();
+            server.wait_connected(2).await;
+
+            let mentions = server.wait_requests("notes/mentions", 3).await;
+            (mentions, server.wait_requests("notes/create", 1).await)
+        })
+        .await;
+
+        assert_eq!(
+            mentions,
+            [
+                json!({ "i": "misskey-token", "limit": 1, "sinceId": null }),
+                json!({ "i": "misskey-token", "limit": 100, "sinceId": null }),
+                json!({ "i": "misskey-token", "limit": 100, "sinceId": "9k00000001" }),
+            ]
+        );
+        assert_eq!(replies, [invite_reply_body("9k00000001", "code1")]);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_mention() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+        let account = config(&server).misskey_account();
+        let last_note_id = || {
+            let cursor = storage.find_mention_cursor(&account).unwrap();
+            cursor.unwrap().last_note_id
+        };
+        let mention = |id| note_json(id, "user", "@bot help");
+
+        let replies = watch(&server, &storage, &provider, async {
+            server.wait_connected(1).await;
+            server.wait_requests("notes/mentions", 1).await;
+
+            // The reply fails, and the cursor stays before the mention after the next one.
+            server.fail_next("notes/create", 1);
+            server.mention(mention("9k00000001"));
+            server.wait_requests("notes/create", 1).await;
+            server.mention(mention("9k00000002"));
+            server.wait_requests("notes/create", 2).await;
+            assert_eq!(last_note_id(), None);
+
+            // The next backfill retries it.
+            server.set_response(
+                "notes/mentions",
+                json!([mention("9k00000002"), mention("9k00000001")]),
+            );
+            server.disconnect();
+            let replies = server.wait_requests("notes/create", 3).await;
+            // Requested after the backfill advanced the cursor.
+            server.wait_requests("notes/mentions", 3).await;
+            replies
+        })
+        .await;
+
+        let reply_ids: Vec<_> = replies.iter().map(|reply| &reply["replyId"]).collect();
+        assert_eq!(reply_ids, ["9k00000001", "9k00000002", "9k00000001"]);
+        assert_eq!(replies[0], replies[2]);
+        assert_eq!(last_note_id().as_deref(), Some("9k00000002"));
+    }
+
+    #[tokio::test]
+    async fn backfill_after_failed_backfill() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+        let account = config(&server).misskey_account();
+        let mention = |id| note_json(id, "user", "@bot help");
+
+        watch(&server, &storage, &provider, async {
+            server.wait_connected(1).await;
+            server.wait_requests("notes/mentions", 1).await;
+
+            // Mentioned while disconnected, but the backfill fails.
+            server.set_response(
+                "notes/mentions",
+                json!([mention("9k00000002"), mention("9k00000001")]),
+            );
+            server.fail_next("notes/mentions", 1);
+            server.disconnect();
+            server.wait_connected(2).await;
+            server.wait_requests("notes/mentions", 2).await;
+
+            // The stream does not move the cursor past the missed one.
+            server.mention(mention("9k00000002"));
+            server.mention(mention("9k00000003"));
+            server.wait_requests("notes/create", 2).await;
+        })
+        .await;
+        let cursor = storage.find_mention_cursor(&account).unwrap();
+        assert_eq!(cursor, Some(MentionCursor { last_note_id: None }));
+
+        // The backfill after restart processes it.
+        let (mentions, replies) = watch(&server, &storage, &provider, async {
+            let replies = server.wait_requests("notes/create", 3).await;
+            (server.wait_requests("notes/mentions", 4).await, replies)
+        })
+        .await;
+
+        let since_ids: Vec<_> = mentions.iter().map(|body| &body["sinceId"]).collect();
+        assert_eq!(
+            since_ids,
+            [
+                &Value::Null,
+                &Value::Null,
+                &Value::Null,
+                &json!("9k00000002")
+            ]
+        );
+        let reply_ids: Vec<_> = replies.iter().map(|reply| &reply["replyId"]).collect();
+        assert_eq!(reply_ids, ["9k00000002", "9k00000003", "9k00000001"]);
+    }
+
+    #[tokio::test]
+    async fn backfill_after_restart() {
+        let server = MockMisskey::start().await;
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let provider = FakeInviteProvider::default();
+        let cursor = MentionCursor {
+            last_note_id: Some("9k00000001".to_string()),
+        };
+        let account = config(&server).misskey_account();
+        storage.save_mention_cursor(&account, &cursor).unwrap();
+
+        // Mentioned while stopped.
+        server.set_response(
+            "notes/mentions",
+            json!([note_json("9k00000002", "user", "@bot invite")]),
+        );
+
+        let replies = watch(&server, &storage, &provider, async {
+            server.wait_requests("notes/create", 1).await
+        })
+        .await;
+
+        assert_eq!(replies, [invite_reply_body("9k00000002", "code1")]);
+        assert_eq!(
+            server.wait_requests("notes/mentions", 1).await[0],
+            json!({ "i": "misskey-token", "limit": 100, "sinceId": "9k00000001" })
+        );
+    }
+}
@@ -0,0 +1,917 @@
+use std::{error::Error, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::api_misskey::Note;
+
+/// Schema migrations, applied in order. The number of applied ones is kept in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE invite_requests (
+        note_id TEXT NOT NULL PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        host TEXT,
+        invite_code TEXT,
+        reason TEXT,
+        requested_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        outcome TEXT NOT NULL
+    );
+    CREATE INDEX invite_requests_user_id ON invite_requests (user_id, requested_at);
+    CREATE INDEX invite_requests_invite_code ON invite_requests (invite_code);",
+    "ALTER TABLE invite_requests ADD COLUMN policy TEXT;",
+    "CREATE TABLE pending_requests (
+        notice_note_id TEXT NOT NULL PRIMARY KEY,
+        request_note_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        policy TEXT,
+        requested_at INTEGER NOT NULL
+    );
+    CREATE INDEX pending_requests_user_id ON pending_requests (user_id);",
+    "CREATE TABLE member_links (
+        discord_user_id INTEGER NOT NULL PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        user_id TEXT NOT NULL,
+        invite_code TEXT NOT NULL,
+        joined_at INTEGER NOT NULL
+    );
+    CREATE INDEX member_links_user_id ON member_links (user_id);",
+    "ALTER TABLE invite_requests ADD COLUMN revoked_at INTEGER;",
+    "ALTER TABLE invite_requests ADD COLUMN account TEXT;",
+    "CREATE TABLE processed_notes (
+        account TEXT NOT NULL,
+        note_id TEXT NOT NULL,
+        processed_at INTEGER NOT NULL,
+        PRIMARY KEY (account, note_id)
+    );
+    CREATE INDEX processed_notes_processed_at ON processed_notes (processed_at);",
+    "CREATE TABLE member_links_by_guild (
+        discord_user_id INTEGER NOT NULL,
+        guild_id INTEGER NOT NULL,
+        user_id TEXT NOT NULL,
+        invite_code TEXT NOT NULL,
+        joined_at INTEGER NOT NULL,
+        PRIMARY KEY (discord_user_id, guild_id)
+    );
+    INSERT INTO member_links_by_guild SELECT discord_user_id, guild_id, user_id, invite_code, joined_at FROM member_links;
+    DROP TABLE member_links;
+    ALTER TABLE member_links_by_guild RENAME TO member_links;
+    CREATE INDEX member_links_user_id ON member_links (user_id);",
+    "ALTER TABLE pending_requests ADD COLUMN account TEXT;",
+    "CREATE TABLE mention_cursors (
+        account TEXT NOT NULL PRIMARY KEY,
+        last_note_id TEXT
+    );",
+    "CREATE TABLE invite_requests_by_account (
+        note_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        host TEXT,
+        invite_code TEXT,
+        reason TEXT,
+        requested_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        outcome TEXT NOT NULL,
+        policy TEXT,
+        revoked_at INTEGER,
+        account TEXT,
+        PRIMARY KEY (account, note_id)
+    );
+    INSERT INTO invite_requests_by_account
+        SELECT note_id, user_id, username, host, invite_code, reason, requested_at, expires_at, outcome, policy, revoked_at, account
+        FROM invite_requests;
+    DROP TABLE invite_requests;
+    ALTER TABLE invite_requests_by_account RENAME TO invite_requests;
+    CREATE INDEX invite_requests_user_id ON invite_requests (user_id, requested_at);
+    CREATE INDEX invite_requests_invite_code ON invite_requests (invite_code);",
+];
+
+/// Condition of the issued invites which are not expired, used or revoked yet at `?1`.
+const OUTSTANDING_INVITE: &str = "outcome = 'issued'
+    AND revoked_at IS NULL
+    AND (expires_at IS NULL OR expires_at > ?1)
+    AND invite_code NOT IN (SELECT invite_code FROM member_links)";
+
+/// Processed notes are forgotten after this, long enough for the redelivery and the backfill.
+const PROCESSED_NOTE_RETENTION_DAYS: i64 = 7;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum InviteOutcome {
+    Issued,
+    Reissued,
+    Pending,
+    Denied,
+    /// The pending request cancelled by the user.
+    Cancelled,
+    RejectedRemoteUser,
+    RejectedDeniedUser,
+    RejectedDeniedHost,
+    RejectedRateLimited,
+    RejectedIneligible,
+    RejectedRole,
+    RejectedRelation,
+    Failed,
+}
+
+impl InviteOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            InviteOutcome::Issued => "issued",
+            InviteOutcome::Reissued => "reissued",
+            InviteOutcome::Pending => "pending",
+            InviteOutcome::Denied => "denied",
+            InviteOutcome::Cancelled => "cancelled",
+            InviteOutcome::RejectedRemoteUser => "rejected_remote_user",
+            InviteOutcome::RejectedDeniedUser => "rejected_denied_user",
+            InviteOutcome::RejectedDeniedHost => "rejected_denied_host",
+            InviteOutcome::RejectedRateLimited => "rejected_rate_limited",
+            InviteOutcome::RejectedIneligible => "rejected_ineligible",
+            InviteOutcome::RejectedRole => "rejected_role",
+            InviteOutcome::RejectedRelation => "rejected_relation",
+            InviteOutcome::Failed => "failed",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<InviteOutcome> {
+        match s {
+            "issued" => Some(InviteOutcome::Issued),
+            "reissued" => Some(InviteOutcome::Reissued),
+            "pending" => Some(InviteOutcome::Pending),
+            "denied" => Some(InviteOutcome::Denied),
+            "cancelled" => Some(InviteOutcome::Cancelled),
+            "rejected_remote_user" => Some(InviteOutcome::RejectedRemoteUser),
+            "rejected_denied_user" => Some(InviteOutcome::RejectedDeniedUser),
+            "rejected_denied_host" => Some(InviteOutcome::RejectedDeniedHost),
+            "rejected_rate_limited" => Some(InviteOutcome::RejectedRateLimited),
+            "rejected_ineligible" => Some(InviteOutcome::RejectedIneligible),
+            "rejected_role" => Some(InviteOutcome::RejectedRole),
+            "rejected_relation" => Some(InviteOutcome::RejectedRelation),
+            "failed" => Some(InviteOutcome::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// One invite request, and what the bot did for it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct InviteRecord {
+    /// Misskey account of the bot which received the request, see `Config::misskey_account`.
+    /// `None` for the records before it was kept, until `assign_account`.
+    pub account: Option<String>,
+    pub note_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub host: Option<String>,
+    pub invite_code: Option<String>,
+    pub reason: Option<String>,
+    /// Name of the invite policy, `None` for the default one.
+    pub policy: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub outcome: InviteOutcome,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl InviteRecord {
+    pub fn new(
+        account: &str,
+        note: &Note,
+        requested_at: DateTime<Utc>,
+        outcome: InviteOutcome,
+    ) -> InviteRecord {
+        InviteRecord {
+            account: Some(account.to_string()),
+            note_id: note.id.clone(),
+            user_id: note.user.id.clone(),
+            username: note.user.username.clone(),
+            host: note.user.host.clone(),
+            invite_code: None,
+            reason: None,
+            policy: None,
+            requested_at,
+            expires_at: None,
+            outcome,
+            revoked_at: None,
+        }
+    }
+
+    /// Host of the bot account, which local users of the record belong to.
+    pub fn account_host(&self) -> Option<&str> {
+        let account = self.account.as_deref()?;
+        account.rsplit('@').next()
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<InviteRecord> {
+        let outcome: String = row.get("outcome")?;
+        let outcome = InviteOutcome::from_str(&outcome).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                row.as_ref().column_index("outcome").unwrap_or_default(),
+                rusqlite::types::Type::Text,
+                format!("unknown outcome: {}", outcome).into(),
+            )
+        })?;
+
+        Ok(InviteRecord {
+            account: row.get("account")?,
+            note_id: row.get("note_id")?,
+            user_id: row.get("user_id")?,
+            username: row.get("username")?,
+            host: row.get("host")?,
+            invite_code: row.get("invite_code")?,
+            reason: row.get("reason")?,
+            policy: row.get("policy")?,
+            requested_at: from_timestamp(row.get("requested_at")?),
+            expires_at: row.get::<_, Option<i64>>("expires_at")?.map(from_timestamp),
+            outcome,
+            revoked_at: row.get::<_, Option<i64>>("revoked_at")?.map(from_timestamp),
+        })
+    }
+}
+
+/// The request waiting for moderators, who decide it by replying or reacting to the notice.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PendingRequest {
+    /// Misskey account of the bot, see `InviteRecord::account`.
+    pub account: String,
+    pub notice_note_id: String,
+    pub request_note_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub policy: Option<String>,
+    pub requested_at: DateTime<Utc>,
+}
+
+impl PendingRequest {
+    fn from_row(row: &Row) -> rusqlite::Result<PendingRequest> {
+        Ok(PendingRequest {
+            account: row.get::<_, Option<String>>("account")?.unwrap_or_default(),
+            notice_note_id: row.get("notice_note_id")?,
+            request_note_id: row.get("request_note_id")?,
+            user_id: row.get("user_id")?,
+            username: row.get("username")?,
+            policy: row.get("policy")?,
+            requested_at: from_timestamp(row.get("requested_at")?),
+        })
+    }
+}
+
+/// The Discord member who joined with the invite issued to the Misskey user.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MemberLink {
+    pub discord_user_id: u64,
+    pub guild_id: u64,
+    pub user_id: String,
+    pub invite_code: String,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// Position of the mentions the bot account received, to backfill the missed ones after reconnection.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct MentionCursor {
+    /// Id of the newest mention received, `None` if none has been received since the watching started.
+    pub last_note_id: Option<String>,
+}
+
+/// The invite ledger, persisted to a SQLite database.
+pub struct RepoStorage {
+    conn: Mutex<Connection>,
+}
+
+impl RepoStorage {
+    pub fn open(path: &str) -> Result<RepoStorage, Box<dyn Error>> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<RepoStorage, Box<dyn Error>> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<RepoStorage, Box<dyn Error>> {
+        migrate(&mut conn)?;
+
+        Ok(RepoStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Assign the records before the account was kept to the account, which was the only one at that time.
+    pub fn assign_account(&self, account: &str) -> Result<(), Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE invite_requests SET account = ?1 WHERE account IS NULL",
+            params![account],
+        )?;
+        conn.execute(
+            "UPDATE pending_requests SET account = ?1 WHERE account IS NULL",
+            params![account],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record_request(&self, record: &InviteRecord) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO invite_requests
+                (note_id, user_id, username, host, invite_code, reason, policy, requested_at, expires_at, outcome, revoked_at, account)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                record.note_id,
+                record.user_id,
+                record.username,
+                record.host,
+                record.invite_code,
+                record.reason,
+                record.policy,
+                record.requested_at.timestamp(),
+                record.expires_at.map(|t| t.timestamp()),
+                record.outcome.as_str(),
+                record.revoked_at.map(|t| t.timestamp()),
+                record.account,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Requests from the user to the bot account, newest first.
+    pub fn find_requests_by_user(
+        &self,
+        account: &str,
+        user_id: &str,
+    ) -> Result<Vec<InviteRecord>, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM invite_requests WHERE account = ?1 AND user_id = ?2
+                ORDER BY requested_at DESC",
+        )?;
+        let records = stmt
+            .query_map(params![account, user_id], InviteRecord::from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(records)
+    }
+
+    /// The request which the invite was issued for.
+    pub fn find_request_by_code(&self, code: &str) -> Result<Option<InviteRecord>, Box<dyn Error>> {
+        let record = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT * FROM invite_requests WHERE invite_code = ?1 AND outcome = 'issued'",
+                params![code],
+                InviteRecord::from_row,
+            )
+            .optional()?;
+
+        Ok(record)
+    }
+
+    /// The request of the note received by the account.
+    pub fn find_request_by_note(
+        &self,
+        account: &str,
+        note_id: &str,
+    ) -> Result<Option<InviteRecord>, Box<dyn Error>> {
+        let record = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT * FROM invite_requests WHERE account = ?1 AND note_id = ?2",
+                params![account, note_id],
+                InviteRecord::from_row,
+            )
+            .optional()?;
+
+        Ok(record)
+    }
+
+    pub fn add_pending(&self, pending: &PendingRequest) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO pending_requests
+                (notice_note_id, request_note_id, user_id, username, policy, requested_at, account)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                pending.notice_note_id,
+                pending.request_note_id,
+                pending.user_id,
+                pending.username,
+                pending.policy,
+                pending.requested_at.timestamp(),
+                pending.account,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_pending_by_notice(
+        &self,
+        account: &str,
+        notice_note_id: &str,
+    ) -> Result<Option<PendingRequest>, Box<dyn Error>> {
+        let pending = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT * FROM pending_requests WHERE account = ?1 AND notice_note_id = ?2",
+                params![account, notice_note_id],
+                PendingRequest::from_row,
+            )
+            .optional()?;
+
+        Ok(pending)
+    }
+
+    pub fn find_pending_by_user(
+        &self,
+        account: &str,
+        user_id: &str,
+    ) -> Result<Option<PendingRequest>, Box<dyn Error>> {
+        let pending = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT * FROM pending_requests WHERE account = ?1 AND user_id = ?2
+                    ORDER BY requested_at LIMIT 1",
+                params![account, user_id],
+                PendingRequest::from_row,
+            )
+            .optional()?;
+
+        Ok(pending)
+    }
+
+    pub fn remove_pending(&self, notice_note_id: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM pending_requests WHERE notice_note_id = ?1",
+            params![notice_note_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Issued invites which are not expired, used or revoked yet.
+    pub fn find_outstanding_invites(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<InviteRecord>, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM invite_requests WHERE {} ORDER BY requested_at",
+            OUTSTANDING_INVITE
+        ))?;
+        let records = stmt
+            .query_map(params![now.timestamp()], InviteRecord::from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(records)
+    }
+
+    /// Outstanding invites of the user requested to the bot account, see `find_outstanding_invites`.
+    pub fn find_outstanding_invites_by_user(
+        &self,
+        account: &str,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<InviteRecord>, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM invite_requests WHERE account = ?2 AND user_id = ?3 AND {}
+                ORDER BY requested_at",
+            OUTSTANDING_INVITE
+        ))?;
+        let records = stmt
+            .query_map(
+                params![now.timestamp(), account, user_id],
+                InviteRecord::from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+
+        Ok(records)
+    }
+
+    pub fn mark_revoked(
+        &self,
+        code: &str,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE invite_requests SET revoked_at = ?2 WHERE invite_code = ?1 AND outcome = 'issued'",
+            params![code, revoked_at.timestamp()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn link_member(&self, link: &MemberLink) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO member_links
+                (discord_user_id, guild_id, user_id, invite_code, joined_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                link.discord_user_id,
+                link.guild_id,
+                link.user_id,
+                link.invite_code,
+                link.joined_at.timestamp(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Mark the note processed by the account, and returns `false` if it is already marked.
+    pub fn mark_processed(
+        &self,
+        account: &str,
+        note_id: &str,
+        processed_at: DateTime<Utc>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let forget_before = processed_at - Duration::days(PROCESSED_NOTE_RETENTION_DAYS);
+        conn.execute(
+            "DELETE FROM processed_notes WHERE processed_at < ?1",
+            params![forget_before.timestamp()],
+        )?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO processed_notes (account, note_id, processed_at)
+                VALUES (?1, ?2, ?3)",
+            params![account, note_id, processed_at.timestamp()],
+        )?;
+
+        Ok(inserted == 1)
+    }
+
+    /// Clear the mark, to process the note again.
+    pub fn unmark_processed(&self, account: &str, note_id: &str) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM processed_notes WHERE account = ?1 AND note_id = ?2",
+            params![account, note_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Cursor of the account, `None` if it has never watched the mentions.
+    pub fn find_mention_cursor(
+        &self,
+        account: &str,
+    ) -> Result<Option<MentionCursor>, Box<dyn Error>> {
+        let cursor = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT last_note_id FROM mention_cursors WHERE account = ?1",
+                params![account],
+                |row| {
+                    Ok(MentionCursor {
+                        last_note_id: row.get(0)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(cursor)
+    }
+
+    pub fn save_mention_cursor(
+        &self,
+        account: &str,
+        cursor: &MentionCursor,
+    ) -> Result<(), Box<dyn Error>> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO mention_cursors (account, last_note_id) VALUES (?1, ?2)",
+            params![account, cursor.last_note_id],
+        )?;
+
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn from_timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    const ACCOUNT: &str = "@bot@example.com";
+
+    fn record(note_id: &str, user_id: &str, requested_at: i64) -> InviteRecord {
+        InviteRecord {
+            account: Some(ACCOUNT.to_string()),
+            note_id: note_id.to_string(),
+            user_id: user_id.to_string(),
+            username: "user".to_string(),
+            host: None,
+            invite_code: Some(format!("code-{}", note_id)),
+            reason: Some("@user@example.com (user)".to_string()),
+            policy: None,
+            requested_at: from_timestamp(requested_at),
+            expires_at: Some(from_timestamp(requested_at) + Duration::hours(1)),
+            outcome: InviteOutcome::Issued,
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn record_and_find_by_user() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        storage.record_request(&record("b", "user1", 200)).unwrap();
+        storage.record_request(&record("c", "user2", 300)).unwrap();
+
+        assert_eq!(
+            storage.find_requests_by_user(ACCOUNT, "user1").unwrap(),
+            vec![record("b", "user1", 200), record("a", "user1", 100)]
+        );
+        assert_eq!(
+            storage.find_requests_by_user(ACCOUNT, "user3").unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn scoped_by_account() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        // The same note can mention several bot accounts.
+        storage
+            .record_request(&InviteRecord {
+                account: Some("@other@example.com".to_string()),
+                ..record("a", "user1", 200)
+            })
+            .unwrap();
+        storage
+            .record_request(&InviteRecord {
+                account: None,
+                ..record("c", "user1", 300)
+            })
+            .unwrap();
+
+        assert_eq!(
+            storage.find_requests_by_user(ACCOUNT, "user1").unwrap(),
+            vec![record("a", "user1", 100)]
+        );
+        assert_eq!(
+            storage
+                .find_request_by_note("@other@example.com", "a")
+                .unwrap()
+                .map(|record| record.requested_at),
+            Some(from_timestamp(200))
+        );
+        assert_eq!(
+            storage
+                .find_outstanding_invites_by_user("@other@example.com", "user1", from_timestamp(0))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Records before the account was kept.
+        storage.assign_account(ACCOUNT).unwrap();
+        assert_eq!(
+            storage.find_requests_by_user(ACCOUNT, "user1").unwrap(),
+            vec![record("c", "user1", 300), record("a", "user1", 100)]
+        );
+        assert_eq!(
+            record("a", "user1", 100).account_host(),
+            Some("example.com")
+        );
+    }
+
+    #[test]
+    fn record_and_find_by_code() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        storage
+            .record_request(&InviteRecord {
+                outcome: InviteOutcome::Reissued,
+                ..record("b", "user1", 200)
+            })
+            .unwrap();
+
+        assert_eq!(
+            storage.find_request_by_code("code-a").unwrap(),
+            Some(record("a", "user1", 100))
+        );
+        assert_eq!(storage.find_request_by_code("code-b").unwrap(), None);
+
+        assert_eq!(
+            storage.find_request_by_note(ACCOUNT, "a").unwrap(),
+            Some(record("a", "user1", 100))
+        );
+        assert_eq!(storage.find_request_by_note(ACCOUNT, "c").unwrap(), None);
+        assert_eq!(storage.find_request_by_note("@bot@b", "a").unwrap(), None);
+    }
+
+    #[test]
+    fn outstanding_invites() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        storage.record_request(&record("b", "user1", 200)).unwrap();
+        storage.record_request(&record("c", "user2", 300)).unwrap();
+        storage.record_request(&record("d", "user2", 400)).unwrap();
+        storage
+            .record_request(&InviteRecord {
+                outcome: InviteOutcome::Failed,
+                ..record("e", "user2", 500)
+            })
+            .unwrap();
+
+        storage.mark_revoked("code-b", from_timestamp(250)).unwrap();
+        storage
+            .link_member(&MemberLink {
+                discord_user_id: 1,
+                guild_id: 2,
+                user_id: "user2".to_string(),
+                invite_code: "code-c".to_string(),
+                joined_at: from_timestamp(350),
+            })
+            .unwrap();
+
+        // "a" is expired.
+        assert_eq!(
+            storage
+                .find_outstanding_invites(from_timestamp(100 + 3600))
+                .unwrap(),
+            vec![record("d", "user2", 400)]
+        );
+        assert_eq!(
+            storage
+                .find_outstanding_invites_by_user(ACCOUNT, "user2", from_timestamp(100))
+                .unwrap(),
+            vec![record("d", "user2", 400)]
+        );
+        assert_eq!(
+            storage
+                .find_outstanding_invites_by_user(ACCOUNT, "user1", from_timestamp(100))
+                .unwrap(),
+            vec![record("a", "user1", 100)]
+        );
+        assert_eq!(
+            storage.find_requests_by_user(ACCOUNT, "user1").unwrap()[0].revoked_at,
+            Some(from_timestamp(250))
+        );
+    }
+
+    #[test]
+    fn member_links_by_guild() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        storage.record_request(&record("b", "user1", 100)).unwrap();
+
+        for (guild_id, code) in [(2, "code-a"), (3, "code-b")] {
+            storage
+                .link_member(&MemberLink {
+                    discord_user_id: 1,
+                    guild_id,
+                    user_id: "user1".to_string(),
+                    invite_code: code.to_string(),
+                    joined_at: from_timestamp(150),
+                })
+                .unwrap();
+        }
+
+        // Both are used, by the same Discord user in two guilds.
+        assert_eq!(
+            storage
+                .find_outstanding_invites(from_timestamp(150))
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn pending() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let pending = PendingRequest {
+            account: ACCOUNT.to_string(),
+            notice_note_id: "notice".to_string(),
+            request_note_id: "request".to_string(),
+            user_id: "user1".to_string(),
+            username: "user".to_string(),
+            policy: Some("stream".to_string()),
+            requested_at: from_timestamp(100),
+        };
+
+        storage.add_pending(&pending).unwrap();
+
+        assert_eq!(
+            storage.find_pending_by_notice(ACCOUNT, "notice").unwrap(),
+            Some(pending.clone())
+        );
+        assert_eq!(
+            storage.find_pending_by_user(ACCOUNT, "user1").unwrap(),
+            Some(pending)
+        );
+        assert_eq!(
+            storage.find_pending_by_user(ACCOUNT, "user2").unwrap(),
+            None
+        );
+        assert_eq!(
+            storage
+                .find_pending_by_user("@other@example.com", "user1")
+                .unwrap(),
+            None
+        );
+
+        storage.remove_pending("notice").unwrap();
+
+        assert_eq!(
+            storage.find_pending_by_notice(ACCOUNT, "notice").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn processed_notes() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        let now = from_timestamp(100);
+
+        assert!(storage.mark_processed("@bot@a", "note", now).unwrap());
+        assert!(!storage.mark_processed("@bot@a", "note", now).unwrap());
+        assert!(storage.mark_processed("@bot@b", "note", now).unwrap());
+
+        let later = now + Duration::days(PROCESSED_NOTE_RETENTION_DAYS) + Duration::seconds(1);
+        assert!(storage.mark_processed("@bot@a", "note", later).unwrap());
+
+        storage.unmark_processed("@bot@a", "note").unwrap();
+        assert!(storage.mark_processed("@bot@a", "note", later).unwrap());
+    }
+
+    #[test]
+    fn mention_cursors() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        assert_eq!(storage.find_mention_cursor("@bot@a").unwrap(), None);
+
+        storage
+            .save_mention_cursor("@bot@a", &MentionCursor::default())
+            .unwrap();
+        assert_eq!(
+            storage.find_mention_cursor("@bot@a").unwrap(),
+            Some(MentionCursor::default())
+        );
+
+        let cursor = MentionCursor {
+            last_note_id: Some("note".to_string()),
+        };
+        storage.save_mention_cursor("@bot@a", &cursor).unwrap();
+        assert_eq!(storage.find_mention_cursor("@bot@a").unwrap(), Some(cursor));
+        assert_eq!(storage.find_mention_cursor("@bot@b").unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_outcome() {
+        let storage = RepoStorage::open_in_memory().unwrap();
+        storage.record_request(&record("a", "user1", 100)).unwrap();
+        storage
+            .conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE invite_requests SET outcome = 'unknown'", [])
+            .unwrap();
+
+        let err = storage.find_requests_by_user(ACCOUNT, "user1").unwrap_err();
+        let Some(rusqlite::Error::FromSqlConversionFailure(index, _, _)) = err.downcast_ref()
+        else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(*index, 8);
+    }
+
+    #[test]
+    fn migrate_twice() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+    }
+}
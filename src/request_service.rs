@@ -0,0 +1,870 @@
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use futures::Future;
+
+use crate::{
+    access_list::{check_access, Access, AccessList},
+    api_misskey::{Note, UserDetailed, UserRelation},
+    command::{parse_command, Command},
+    config::{
+        AccessListConfig, ApprovalConfig, CommandsConfig, Config, EligibilityConfig,
+        RateLimitConfig, RelationConfig, RolesConfig,
+    },
+    eligibility::{
+        check_eligibility, check_roles, is_relation_satisfied, Ineligible, RoleRejection,
+    },
+    invite_provider::InviteProvider,
+    rate_limit::next_allowed_time,
+    repo_storage::{InviteOutcome, InviteRecord, RepoStorage},
+    routing::choose_policy,
+};
+
+/// What to do with the mention, with the config section of the reply.
+#[derive(PartialEq, Eq, Debug)]
+pub enum RequestDecision<'a> {
+    /// Not a request to the bot, such as a reply or a mention of others.
+    Ignore,
+    Help(&'a CommandsConfig),
+    Status(&'a CommandsConfig),
+    Cancel(&'a CommandsConfig),
+    /// Revoke the invites of the user, requested by an admin.
+    Revoke {
+        target: String,
+    },
+    RejectRemote,
+    RejectDeniedUser(&'a AccessListConfig),
+    RejectDeniedHost(&'a AccessListConfig),
+    RejectRole(&'a RolesConfig, RoleRejection),
+    RejectIneligible(&'a EligibilityConfig, Ineligible),
+    RejectRelation(&'a RelationConfig, UserRelation),
+    RejectRateLimited {
+        rate_limit: &'a RateLimitConfig,
+        retry_after: DateTime<Utc>,
+    },
+    /// Resend the invite issued before, which is still valid.
+    Reissue {
+        code: String,
+        issued: Box<InviteRecord>,
+    },
+    /// Ask moderators with the notice, or only reply if they are already asked about the user.
+    AskApproval {
+        approval: &'a ApprovalConfig,
+        policy: Option<String>,
+        notify: bool,
+    },
+    Invite {
+        policy: Option<String>,
+    },
+}
+
+/// Decides what to do with the mentions, without replying, recording or issuing anything.
+pub struct RequestService<'a> {
+    config: &'a Config,
+    invite_provider: &'a dyn InviteProvider,
+    repo_storage: &'a RepoStorage,
+}
+
+impl<'a> RequestService<'a> {
+    pub fn new(
+        config: &'a Config,
+        invite_provider: &'a dyn InviteProvider,
+        repo_storage: &'a RepoStorage,
+    ) -> RequestService<'a> {
+        RequestService {
+            config,
+            invite_provider,
+            repo_storage,
+        }
+    }
+
+    /// Whether the note is a mention to the bot with text, not a reply.
+    pub fn is_request(&self, note: &Note) -> bool {
+        note.reply_id.is_none()
+            && note
+                .text
+                .as_ref()
+                .is_some_and(|text| self.strip_mention(text).is_some())
+    }
+
+    /// The text after the mention of the bot, which is either `@bot` or
+    /// `@bot@host` in full. `None` if the text starts with another mention,
+    /// such as `@bot2`.
+    fn strip_mention<'t>(&self, text: &'t str) -> Option<&'t str> {
+        let config = self.config;
+        let rest = text.strip_prefix(&config.misskey_bot_username)?;
+        let host = format!("@{}", config.misskey_host);
+        let rest = match rest.get(..host.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(&host) => &rest[host.len()..],
+            _ => rest,
+        };
+        (rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+    }
+
+    /// Whether `decide` or the replies need the details of the requester.
+    pub fn needs_user(&self) -> bool {
+        let config = self.config;
+        let routes_by_role = config
+            .invite_policies
+            .values()
+            .any(|policy| !policy.roles.is_empty());
+
+        config.eligibility.is_some()
+            || config.roles.is_some()
+            || !config.messages.is_empty()
+            || routes_by_role
+    }
+
+    /// `user` is the requester if `needs_user`, and `relation` is awaited only if `[relation]` is configured.
+    /// `access_list` is the list of `[access_list]`, and ignored without it.
+    pub async fn decide(
+        &self,
+        note: &Note,
+        user: Option<&UserDetailed>,
+        access_list: Option<&AccessList>,
+        relation: impl Future<Output = Result<UserRelation, Box<dyn Error>>>,
+        now: DateTime<Utc>,
+    ) -> Result<RequestDecision<'a>, Box<dyn Error>> {
+        let config = self.config;
+
+        if !self.is_request(note) {
+            return Ok(RequestDecision::Ignore);
+        }
+        let text = note.text.as_deref().unwrap_or_default();
+
+        let command = parse_command(
+            self.strip_mention(text).unwrap_or_default(),
+            &config.invite_policies,
+        );
+        let is_admin = config
+            .revocation
+            .as_ref()
+            .is_some_and(|revocation| revocation.admin_user_ids.contains(&note.user.id));
+
+        let policy_name = match (command, &config.commands) {
+            (Command::Invite { policy }, _) => policy,
+            (
+                Command::Revoke {
+                    target: Some(target),
+                },
+                _,
+            ) if is_admin => {
+                return Ok(RequestDecision::Revoke {
+                    target: target.to_string(),
+                })
+            }
+            (Command::Status, Some(commands)) => return Ok(RequestDecision::Status(commands)),
+            (Command::Cancel, Some(commands)) => return Ok(RequestDecision::Cancel(commands)),
+            (_, Some(commands)) => return Ok(RequestDecision::Help(commands)),
+            // Every mention is an invite request without the commands.
+            (_, None) => None,
+        };
+        let policy_name = choose_policy(config, policy_name, note.user.host.as_deref(), user);
+        let policy = policy_name.map(str::to_string);
+
+        // Only local users, or allowed remote users can request.
+        match access_list.zip(config.access_list.as_ref()) {
+            Some((access_list, list_config)) => match check_access(access_list, &note.user) {
+                Access::Allowed => {}
+                Access::DeniedUser => return Ok(RequestDecision::RejectDeniedUser(list_config)),
+                Access::DeniedHost => return Ok(RequestDecision::RejectDeniedHost(list_config)),
+                Access::RemoteUser => return Ok(RequestDecision::RejectRemote),
+            },
+            None if note.user.host.is_some() => return Ok(RequestDecision::RejectRemote),
+            None => {}
+        }
+
+        if let (Some(roles), Some(user)) = (&config.roles, user) {
+            if let Some(rejection) = check_roles(roles, user) {
+                return Ok(RequestDecision::RejectRole(roles, rejection));
+            }
+        }
+
+        if let (Some(eligibility), Some(user)) = (&config.eligibility, user) {
+            if let Some(ineligible) = check_eligibility(eligibility, user, now) {
+                return Ok(RequestDecision::RejectIneligible(eligibility, ineligible));
+            }
+        }
+
+        if let Some(relation_config) = &config.relation {
+            let relation = relation.await?;
+            if !is_relation_satisfied(relation_config.require, &relation) {
+                return Ok(RequestDecision::RejectRelation(relation_config, relation));
+            }
+        }
+
+        let issued: Vec<_> = self
+            .repo_storage
+            .find_requests_by_user(&config.misskey_account(), &note.user.id)?
+            .into_iter()
+            .filter(|record| record.outcome == InviteOutcome::Issued)
+            .collect();
+
+        // Resend the invite issued before if it is still valid. Only single-use invites are resent,
+        // because others may have been used by someone else, and are not told apart from unused ones.
+        let invite_policy = policy_name
+            .and_then(|name| config.invite_policies.get(name))
+            .unwrap_or(&config.invite);
+        let reissuable = if invite_policy.max_uses == 1 {
+            issued.as_slice()
+        } else {
+            &[]
+        };
+        for record in reissuable {
+            let Some(code) = &record.invite_code else {
+                continue;
+            };
+            if record.policy.as_deref() != policy_name || record.revoked_at.is_some() {
+                continue;
+            }
+            if record.expires_at.is_some_and(|t| t <= now) {
+                continue;
+            }
+            if !self.invite_provider.is_invite_valid(code).await? {
+                continue;
+            }
+
+            return Ok(RequestDecision::Reissue {
+                code: code.clone(),
+                issued: Box::new(record.clone()),
+            });
+        }
+
+        if let (Some(rate_limit), Some(retry_after)) =
+            (&config.rate_limit, self.retry_after(&issued, now))
+        {
+            return Ok(RequestDecision::RejectRateLimited {
+                rate_limit,
+                retry_after,
+            });
+        }
+
+        if let Some(approval) = &config.approval {
+            // Ask moderators once, even if the user requests again while waiting.
+            let notify = self
+                .repo_storage
+                .find_pending_by_user(&config.misskey_account(), &note.user.id)?
+                .is_none();
+
+            return Ok(RequestDecision::AskApproval {
+                approval,
+                policy,
+                notify,
+            });
+        }
+
+        Ok(RequestDecision::Invite { policy })
+    }
+
+    /// The time when the user can get another invite, or `None` if it can be issued now.
+    pub fn rate_limited_until(
+        &self,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let issued: Vec<_> = self
+            .repo_storage
+            .find_requests_by_user(&self.config.misskey_account(), user_id)?
+            .into_iter()
+            .filter(|record| record.outcome == InviteOutcome::Issued)
+            .collect();
+
+        Ok(self.retry_after(&issued, now))
+    }
+
+    fn retry_after(&self, issued: &[InviteRecord], now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let rate_limit = self.config.rate_limit.as_ref()?;
+        let issued_at: Vec<_> = issued.iter().map(|record| record.requested_at).collect();
+
+        next_allowed_time(&rate_limit.rules, &issued_at, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{RequestDecision, RequestService};
+    use crate::{
+        access_list::AccessList,
+        api_misskey::{Note, User, UserDetailed, UserRelation},
+        config::{Config, InvitePolicy},
+        eligibility::{Ineligible, RoleRejection},
+        invite_provider::{FakeInviteProvider, InviteProvider},
+        repo_storage::{InviteOutcome, InviteRecord, PendingRequest, RepoStorage},
+        test_fixtures::{full_config, hours, user},
+    };
+
+    /// Config without the optional sections.
+    fn config() -> Config {
+        Config {
+            access_list: None,
+            rate_limit: None,
+            eligibility: None,
+            roles: None,
+            relation: None,
+            approval: None,
+            revocation: None,
+            commands: None,
+            invite_policies: Default::default(),
+            ..full_config()
+        }
+    }
+
+    fn note(text: &str, host: Option<&str>) -> Note {
+        Note {
+            id: "note".to_string(),
+            text: Some(text.to_string()),
+            user: User {
+                id: "user".to_string(),
+                username: "user".to_string(),
+                host: host.map(str::to_string),
+            },
+            reply_id: None,
+        }
+    }
+
+    fn relation(is_following: bool, is_followed: bool) -> UserRelation {
+        UserRelation {
+            id: "user".to_string(),
+            is_following,
+            is_followed,
+        }
+    }
+
+    fn issued(code: &str, requested_at: DateTime<Utc>) -> InviteRecord {
+        let mut record = InviteRecord::new(
+            &config().misskey_account(),
+            &note("@test", None),
+            requested_at,
+            InviteOutcome::Issued,
+        );
+        record.note_id = format!("note-{}", code);
+        record.invite_code = Some(code.to_string());
+        record.expires_at = Some(requested_at + Duration::hours(1));
+        record
+    }
+
+    /// The storage and the invite provider shared by the requests of a test.
+    struct Fixture {
+        storage: RepoStorage,
+        provider: FakeInviteProvider,
+    }
+
+    impl Fixture {
+        fn new() -> Fixture {
+            Fixture {
+                storage: RepoStorage::open_in_memory().unwrap(),
+                provider: FakeInviteProvider::default(),
+            }
+        }
+
+        /// Request by the user without roles at `hours(100)`, without the access list or the relation.
+        fn request<'a>(&'a self, config: &'a Config, note: Note) -> Request<'a> {
+            Request {
+                fixture: self,
+                config,
+                note,
+                user: Some(user(&[])),
+                access_list: None,
+                relation: None,
+                now: hours(100),
+            }
+        }
+
+        /// Decide the local user's request of the text, see `request`.
+        async fn decide<'a>(&'a self, config: &'a Config, text: &str) -> RequestDecision<'a> {
+            self.request(config, note(text, None)).decide().await
+        }
+    }
+
+    /// Arguments of `RequestService::decide`, changed from the defaults of `Fixture::request`.
+    struct Request<'a> {
+        fixture: &'a Fixture,
+        config: &'a Config,
+        note: Note,
+        user: Option<UserDetailed>,
+        access_list: Option<&'a AccessList>,
+        /// `None` if it must not be fetched.
+        relation: Option<UserRelation>,
+        now: DateTime<Utc>,
+    }
+
+    impl<'a> Request<'a> {
+        fn user(self, user: Option<UserDetailed>) -> Request<'a> {
+            Request { user, ..self }
+        }
+
+        fn access_list(self, access_list: &'a AccessList) -> Request<'a> {
+            Request {
+                access_list: Some(access_list),
+                ..self
+            }
+        }
+
+        fn relation(self, relation: UserRelation) -> Request<'a> {
+            Request {
+                relation: Some(relation),
+                ..self
+            }
+        }
+
+        fn at(self, now: DateTime<Utc>) -> Request<'a> {
+            Request { now, ..self }
+        }
+
+        async fn decide(self) -> RequestDecision<'a> {
+            let Request {
+                fixture,
+                config,
+                note,
+                user,
+                access_list,
+                relation,
+                now,
+            } = self;
+            let service = RequestService::new(config, &fixture.provider, &fixture.storage);
+            let relation = async move {
+                Ok::<_, Box<dyn Error>>(
+                    relation.expect("relation is fetched only if it is configured"),
+                )
+            };
+
+            service
+                .decide(&note, user.as_ref(), access_list, relation, now)
+                .await
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn ignore() {
+        let fixture = Fixture::new();
+        let config = config();
+
+        let mut reply = note("@test", None);
+        reply.reply_id = Some("parent".to_string());
+        assert_eq!(
+            fixture.request(&config, reply).decide().await,
+            RequestDecision::Ignore
+        );
+
+        let mut no_text = note("", None);
+        no_text.text = None;
+        assert_eq!(
+            fixture.request(&config, no_text).decide().await,
+            RequestDecision::Ignore
+        );
+
+        assert_eq!(
+            fixture.decide(&config, "@other @test").await,
+            RequestDecision::Ignore
+        );
+    }
+
+    #[tokio::test]
+    async fn invite() {
+        let fixture = Fixture::new();
+        let config = config();
+
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::Invite { policy: None }
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test help").await,
+            RequestDecision::Invite { policy: None }
+        );
+    }
+
+    #[tokio::test]
+    async fn invite_policy() {
+        let fixture = Fixture::new();
+        let config = Config {
+            invite_policies: full_config().invite_policies,
+            ..config()
+        };
+
+        assert_eq!(
+            fixture.decide(&config, "@test invite stream").await,
+            RequestDecision::Invite {
+                policy: Some("stream".to_string())
+            }
+        );
+        assert_eq!(
+            fixture
+                .request(&config, note("@test", None))
+                .user(Some(user(&["gamer"])))
+                .decide()
+                .await,
+            RequestDecision::Invite {
+                policy: Some("gaming".to_string())
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let fixture = Fixture::new();
+        let config = Config {
+            commands: full_config().commands,
+            revocation: full_config().revocation,
+            ..config()
+        };
+        let commands = config.commands.as_ref().unwrap();
+
+        assert_eq!(
+            fixture.decide(&config, "@test help").await,
+            RequestDecision::Help(commands)
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test something").await,
+            RequestDecision::Help(commands)
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test status").await,
+            RequestDecision::Status(commands)
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test cancel").await,
+            RequestDecision::Cancel(commands)
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test invite").await,
+            RequestDecision::Invite { policy: None }
+        );
+
+        // Only admins can revoke.
+        assert_eq!(
+            fixture.decide(&config, "@test revoke @someone").await,
+            RequestDecision::Help(commands)
+        );
+        let mut from_admin = note("@test revoke @someone", None);
+        from_admin.user.id = "admin-id".to_string();
+        assert_eq!(
+            fixture.request(&config, from_admin).decide().await,
+            RequestDecision::Revoke {
+                target: "@someone".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn full_mention() {
+        let fixture = Fixture::new();
+        let config = Config {
+            commands: full_config().commands,
+            ..config()
+        };
+        let commands = config.commands.as_ref().unwrap();
+
+        // Remote users mention the bot with its host.
+        assert_eq!(
+            fixture.decide(&config, "@test@example.com invite").await,
+            RequestDecision::Invite { policy: None }
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test@Example.COM status").await,
+            RequestDecision::Status(commands)
+        );
+
+        // Mentions of other accounts.
+        assert_eq!(
+            fixture
+                .decide(&config, "@test@other.example.com invite")
+                .await,
+            RequestDecision::Ignore
+        );
+        assert_eq!(
+            fixture.decide(&config, "@test2 invite").await,
+            RequestDecision::Ignore
+        );
+        assert_eq!(
+            fixture
+                .decide(&config, "@test@example.community invite")
+                .await,
+            RequestDecision::Ignore
+        );
+    }
+
+    #[tokio::test]
+    async fn access() {
+        let fixture = Fixture::new();
+        let list = AccessList {
+            allow_hosts: vec!["allowed.example.com".to_string()],
+            deny_hosts: vec!["denied.example.com".to_string()],
+            allow_users: vec![],
+            deny_users: vec!["@user".to_string()],
+        };
+        let without_section = config();
+        let config = Config {
+            access_list: full_config().access_list,
+            ..config()
+        };
+        let list_config = config.access_list.as_ref().unwrap();
+        let decide = |config, host| {
+            let request = fixture.request(config, note("@test", host));
+            request.user(None).access_list(&list).decide()
+        };
+
+        // The list is not used without `[access_list]`.
+        assert_eq!(
+            decide(&without_section, None).await,
+            RequestDecision::Invite { policy: None }
+        );
+        assert_eq!(
+            decide(&without_section, Some("allowed.example.com")).await,
+            RequestDecision::RejectRemote
+        );
+
+        assert_eq!(
+            decide(&config, None).await,
+            RequestDecision::RejectDeniedUser(list_config)
+        );
+        assert_eq!(
+            decide(&config, Some("denied.example.com")).await,
+            RequestDecision::RejectDeniedHost(list_config)
+        );
+        assert_eq!(
+            decide(&config, Some("other.example.com")).await,
+            RequestDecision::RejectRemote
+        );
+
+        let mut other_user = note("@test", Some("allowed.example.com"));
+        other_user.user.username = "other".to_string();
+        assert_eq!(
+            fixture
+                .request(&config, other_user)
+                .access_list(&list)
+                .decide()
+                .await,
+            RequestDecision::Invite { policy: None }
+        );
+    }
+
+    #[tokio::test]
+    async fn roles_and_eligibility() {
+        let fixture = Fixture::new();
+        let config = Config {
+            roles: full_config().roles,
+            eligibility: full_config().eligibility,
+            ..config()
+        };
+        let decide = |user, now| {
+            let request = fixture.request(&config, note("@test", None));
+            request.user(Some(user)).at(now).decide()
+        };
+        let (roles, eligibility) = (
+            config.roles.as_ref().unwrap(),
+            config.eligibility.as_ref().unwrap(),
+        );
+
+        assert_eq!(
+            decide(user(&["verified"]), hours(100)).await,
+            RequestDecision::Invite { policy: None }
+        );
+        assert_eq!(
+            decide(user(&[]), hours(100)).await,
+            RequestDecision::RejectRole(roles, RoleRejection::MissingRole)
+        );
+        assert_eq!(
+            decide(user(&["verified"]), hours(10)).await,
+            RequestDecision::RejectIneligible(
+                eligibility,
+                Ineligible::AccountTooNew {
+                    eligible_at: hours(72)
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn relation_required() {
+        let fixture = Fixture::new();
+        let config = Config {
+            relation: full_config().relation,
+            ..config()
+        };
+        let decide = |relation| {
+            let request = fixture.request(&config, note("@test", None));
+            request.user(None).relation(relation).decide()
+        };
+
+        assert_eq!(
+            decide(relation(true, false)).await,
+            RequestDecision::Invite { policy: None }
+        );
+        assert_eq!(
+            decide(relation(false, false)).await,
+            RequestDecision::RejectRelation(
+                config.relation.as_ref().unwrap(),
+                relation(false, false)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn reissue() {
+        let fixture = Fixture::new();
+        let config = Config {
+            invite_policies: full_config().invite_policies,
+            ..config()
+        };
+        let (storage, provider) = (&fixture.storage, &fixture.provider);
+
+        let invite = provider
+            .generate_invite(1234, &InvitePolicy::default(), "reason")
+            .await
+            .unwrap();
+        let record = issued(&invite.code, hours(100) - Duration::minutes(10));
+        storage.record_request(&record).unwrap();
+
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::Reissue {
+                code: invite.code.clone(),
+                issued: Box::new(record)
+            }
+        );
+
+        // Not for another policy.
+        assert_eq!(
+            fixture.decide(&config, "@test invite stream").await,
+            RequestDecision::Invite {
+                policy: Some("stream".to_string())
+            }
+        );
+
+        // Not if the policy allows several uses.
+        let invite_stream = provider
+            .generate_invite(1234, &config.invite_policies["stream"], "reason")
+            .await
+            .unwrap();
+        let mut record_stream = issued(&invite_stream.code, hours(100) - Duration::minutes(5));
+        record_stream.policy = Some("stream".to_string());
+        storage.record_request(&record_stream).unwrap();
+        assert_eq!(
+            fixture.decide(&config, "@test invite stream").await,
+            RequestDecision::Invite {
+                policy: Some("stream".to_string())
+            }
+        );
+
+        // Not if it is deleted from Discord.
+        provider.delete_invite(&invite.code).await.unwrap();
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::Invite { policy: None }
+        );
+    }
+
+    #[tokio::test]
+    async fn reissue_skips_expired_and_revoked() {
+        let fixture = Fixture::new();
+        let config = config();
+
+        for _ in 0..2 {
+            fixture
+                .provider
+                .generate_invite(1234, &InvitePolicy::default(), "reason")
+                .await
+                .unwrap();
+        }
+        // Expired.
+        fixture
+            .storage
+            .record_request(&issued("code1", hours(90)))
+            .unwrap();
+        // Revoked.
+        let mut revoked = issued("code2", hours(100) - Duration::minutes(10));
+        revoked.revoked_at = Some(hours(100) - Duration::minutes(5));
+        fixture.storage.record_request(&revoked).unwrap();
+
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::Invite { policy: None }
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let fixture = Fixture::new();
+        let config = Config {
+            rate_limit: full_config().rate_limit,
+            ..config()
+        };
+
+        // Issued 2 hours ago and expired, and one per day is allowed.
+        fixture
+            .storage
+            .record_request(&issued("code1", hours(98)))
+            .unwrap();
+
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::RejectRateLimited {
+                rate_limit: config.rate_limit.as_ref().unwrap(),
+                retry_after: hours(98 + 24)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn approval() {
+        let fixture = Fixture::new();
+        let config = Config {
+            approval: full_config().approval,
+            ..config()
+        };
+        let approval = config.approval.as_ref().unwrap();
+
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::AskApproval {
+                approval,
+                policy: None,
+                notify: true
+            }
+        );
+
+        fixture
+            .storage
+            .add_pending(&PendingRequest {
+                account: config.misskey_account(),
+                notice_note_id: "notice".to_string(),
+                request_note_id: "note".to_string(),
+                user_id: "user".to_string(),
+                username: "user".to_string(),
+                policy: None,
+                requested_at: hours(99),
+            })
+            .unwrap();
+        assert_eq!(
+            fixture.decide(&config, "@test").await,
+            RequestDecision::AskApproval {
+                approval,
+                policy: None,
+                notify: false
+            }
+        );
+    }
+
+    #[test]
+    fn needs_user() {
+        let Fixture { storage, provider } = Fixture::new();
+
+        let config = Config {
+            messages: Default::default(),
+            ..config()
+        };
+        assert!(!RequestService::new(&config, &provider, &storage).needs_user());
+
+        let config = Config {
+            eligibility: full_config().eligibility,
+            ..config
+        };
+        assert!(RequestService::new(&config, &provider, &storage).needs_user());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::BTreeSet, error::Error, time::Duration};
+
+use chrono::Utc;
+
+use crate::{
+    access_list::is_same_user,
+    api_misskey,
+    invite_provider::InviteProvider,
+    reload::SharedConfig,
+    repo_misskey::RepoMisskey,
+    repo_storage::{InviteRecord, RepoStorage},
+};
+
+/// Delete the invites from Discord, and returns the number of deleted ones.
+pub async fn revoke_invites(
+    invite_provider: &dyn InviteProvider,
+    repo_storage: &RepoStorage,
+    records: &[InviteRecord],
+) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+
+    for code in records
+        .iter()
+        .filter_map(|record| record.invite_code.as_ref())
+    {
+        if invite_provider.delete_invite(code).await? {
+            count += 1;
+        }
+        repo_storage.mark_revoked(code, Utc::now())?;
+
+        log::info!("Revoked invite: `{}`", code);
+    }
+
+    Ok(count)
+}
+
+/// Outstanding invites of the user, written as the user id, `@username` for local users of `misskey_host`, or `@username@host`.
+pub fn find_outstanding_invites_of(
+    repo_storage: &RepoStorage,
+    misskey_host: &str,
+    target: &str,
+) -> Result<Vec<InviteRecord>, Box<dyn Error>> {
+    let records = repo_storage
+        .find_outstanding_invites(Utc::now())?
+        .into_iter()
+        .filter(|record| {
+            // Local users of the accounts on other hosts are remote users for `misskey_host`.
+            let host = record
+                .host
+                .as_deref()
+                .or(record.account_host())
+                .filter(|host| *host != misskey_host);
+
+            is_same_user(target, &record.user_id, &record.username, host)
+        })
+        .collect();
+
+    Ok(records)
+}
+
+/// Revoke outstanding invites of the users who have been suspended or deleted.
+/// Only the invites requested through the Misskey account are checked, because user ids are of the instance.
+async fn revoke_ineligible_invites(
+    account: &str,
+    invite_provider: &dyn InviteProvider,
+    repo_misskey: &RepoMisskey,
+    repo_storage: &RepoStorage,
+) -> Result<(), Box<dyn Error>> {
+    let outstanding: Vec<InviteRecord> = repo_storage
+        .find_outstanding_invites(Utc::now())?
+        .into_iter()
+        .filter(|record| record.account.as_deref() == Some(account))
+        .collect();
+    let user_ids: BTreeSet<&str> = outstanding
+        .iter()
+        .map(|record| record.user_id.as_str())
+        .collect();
+
+    for user_id in user_ids {
+        let reason = match repo_misskey.fetch_user(user_id).await {
+            Ok(user) if user.is_suspended => "suspended",
+            Ok(_) => continue,
+            Err(err)
+                if err
+                    .downcast_ref::<api_misskey::Error>()
+                    .and_then(api_misskey::Error::code)
+                    .is_some_and(|code| code == "NO_SUCH_USER") =>
+            {
+                "deleted"
+            }
+            Err(err) => return Err(err),
+        };
+
+        let records: Vec<InviteRecord> = outstanding
+            .iter()
+            .filter(|record| record.user_id == user_id)
+            .cloned()
+            .collect();
+        let count = revoke_invites(invite_provider, repo_storage, &records).await?;
+
+        log::info!("Revoked {} invites of {} user: {}", count, reason, user_id);
+    }
+
+    Ok(())
+}
+
+/// Check the users periodically while `[revocation]` is configured.
+/// `repo_misskeys` are for each account, in the order of [`SharedConfig::account`].
+pub async fn start_watching_eligibility(
+    config: &SharedConfig,
+    invite_provider: &dyn InviteProvider,
+    repo_misskeys: &[RepoMisskey],
+    repo_storage: &RepoStorage,
+) {
+    loop {
+        let Some(minutes) = config
+            .get()
+            .revocation
+            .as_ref()
+            .map(|revocation| revocation.check_interval_minutes.get())
+        else {
+            // Wait for the config to be reloaded.
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        };
+        tokio::time::sleep(Duration::from_secs(u64::from(minutes) * 60)).await;
+
+        for (index, repo_misskey) in repo_misskeys.iter().enumerate() {
+            let account = config.account(index).misskey_account();
+
+            let result =
+                revoke_ineligible_invites(&account, invite_provider, repo_misskey, repo_storage)
+                    .await;
+
+            if let Err(err) = result {
+                log::warn!(
+                    "Failed to check users who have outstanding invites at {}: {}",
+                    account,
+                    err
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{api_misskey::UserDetailed, config::Config};
+
+/// Name of the invite policy for the request. The policy named in the command is used if any,
+/// otherwise the first one with the requester's instance in `hosts`, or with the requester's role in `roles`.
+pub fn choose_policy<'a>(
+    config: &'a Config,
+    named: Option<&'a str>,
+    host: Option<&str>,
+    user: Option<&UserDetailed>,
+) -> Option<&'a str> {
+    if named.is_some() {
+        return named;
+    }
+
+    let host = host.unwrap_or(&config.misskey_host);
+    let has_role = |name: &String| {
+        user.is_some_and(|user| {
+            user.roles
+                .iter()
+                .any(|role| role.id == *name || role.name == *name)
+        })
+    };
+
+    config
+        .invite_policies
+        .iter()
+        .find(|(_, policy)| {
+            policy.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+                || policy.roles.iter().any(has_role)
+        })
+        .map(|(name, _)| name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::choose_policy;
+    use crate::test_fixtures::{full_config, user};
+
+    #[test]
+    fn choose() {
+        let config = full_config();
+
+        assert_eq!(choose_policy(&config, None, None, None), None);
+        assert_eq!(
+            choose_policy(&config, Some("stream"), Some("game.example.com"), None),
+            Some("stream")
+        );
+        assert_eq!(
+            choose_policy(&config, None, Some("Game.Example.com"), None),
+            Some("gaming")
+        );
+        assert_eq!(
+            choose_policy(&config, None, None, Some(&user(&["gamer"]))),
+            Some("gaming")
+        );
+        assert_eq!(
+            choose_policy(&config, None, None, Some(&user(&["other"]))),
+            None
+        );
+    }
+}
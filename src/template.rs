@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+
+/// Time written in the reply messages.
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// Replace each `{key}` in the template with its value. Unknown placeholders are kept as is.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let var = rest.find('}').and_then(|end| {
+            let key = &rest[1..end];
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, value)| (end, value))
+        });
+
+        match var {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn render_vars() {
+        assert_eq!(
+            render("Hello {name}, {name}! {x}", &[("name", "a"), ("x", "b")]),
+            "Hello a, a! b"
+        );
+    }
+
+    #[test]
+    fn render_unknown_and_unclosed() {
+        assert_eq!(render("{unknown} {x", &[("x", "b")]), "{unknown} {x");
+        assert_eq!(render("{{x}}", &[("x", "b")]), "{b}");
+    }
+}
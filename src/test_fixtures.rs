@@ -0,0 +1,47 @@
+use std::fs::read_to_string;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    api_misskey::{Role, UserDetailed},
+    config::Config,
+};
+
+/// Config with every optional section, shared by the tests. `bot_config-template.toml` has only the required ones.
+pub const FULL_CONFIG_PATH: &str = "testdata/bot_config-full.toml";
+
+pub fn full_config_text() -> String {
+    read_to_string(FULL_CONFIG_PATH).unwrap()
+}
+
+pub fn full_config() -> Config {
+    toml::from_str(&full_config_text()).unwrap()
+}
+
+/// `n` hours after the epoch.
+pub fn hours(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(0, 0).unwrap() + Duration::hours(n)
+}
+
+/// Local user created at the epoch, with the roles whose ids are `{name}-id`.
+pub fn user(roles: &[&str]) -> UserDetailed {
+    UserDetailed {
+        id: "user".to_string(),
+        username: "user".to_string(),
+        host: None,
+        created_at: hours(0),
+        notes_count: 100,
+        followers_count: 100,
+        following_count: 0,
+        roles: roles
+            .iter()
+            .map(|name| Role {
+                id: format!("{}-id", name),
+                name: name.to_string(),
+            })
+            .collect(),
+        is_silenced: false,
+        is_suspended: false,
+        lang: None,
+    }
+}